@@ -513,13 +513,7 @@ impl Frame {
         self.layers.insert(root_fixed_layer_id, layer.clone());
         self.layers.insert(root_scroll_layer_id, layer);
 
-        let background_color = root_pipeline.background_color.and_then(|color| {
-            if color.a > 0.0 {
-                Some(color)
-            } else {
-                None
-            }
-        });
+        let background_color = scene.resolved_background_color(root_pipeline);
 
         let mut frame_builder = FrameBuilder::new(root_pipeline.viewport_size,
                                                   background_color,
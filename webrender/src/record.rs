@@ -55,6 +55,9 @@ pub fn write_msg(frame: u32, msg: &ApiMsg) {
         &ApiMsg::DeleteImage(..)|
         &ApiMsg::SetRootDisplayList(..) |
         &ApiMsg::SetRootPipeline(..) |
+        &ApiMsg::SetCullRect(..) |
+        &ApiMsg::TouchPipeline(..) |
+        &ApiMsg::FreezePipeline(..) |
         &ApiMsg::Scroll(..) |
         &ApiMsg::TickScrollingBounce |
         &ApiMsg::WebGLCommand(..) => {
@@ -9,7 +9,7 @@ use internal_types::{SourceTexture, ResultMsg, RendererFrame};
 use profiler::BackendProfileCounters;
 use record;
 use resource_cache::ResourceCache;
-use scene::Scene;
+use scene::{Scene, TransparentBackgroundPolicy};
 use std::collections::HashMap;
 use std::fs;
 use std::io::{Cursor, Read};
@@ -66,11 +66,14 @@ impl RenderBackend {
                config: FrameBuilderConfig,
                debug: bool,
                enable_recording:bool,
+               transparent_background_policy: TransparentBackgroundPolicy,
                main_thread_dispatcher: Arc<Mutex<Option<Box<RenderDispatcher>>>>,
                vr_compositor_handler: Arc<Mutex<Option<Box<VRCompositorHandler>>>>) -> RenderBackend {
 
         let resource_cache = ResourceCache::new(texture_cache,
                                                 enable_aa);
+        let mut scene = Scene::new();
+        scene.transparent_background_policy = transparent_background_policy;
 
         RenderBackend {
             api_rx: api_rx,
@@ -79,7 +82,7 @@ impl RenderBackend {
             result_tx: result_tx,
             device_pixel_ratio: device_pixel_ratio,
             resource_cache: resource_cache,
-            scene: Scene::new(),
+            scene: scene,
             frame: Frame::new(debug, config),
             next_namespace_id: IdNamespace(1),
             notifier: notifier,
@@ -197,12 +200,16 @@ impl RenderBackend {
                                                           auxiliary_lists_descriptor);
 
                             let frame = profile_counters.total_time.profile(|| {
-                                self.scene.set_root_display_list(pipeline_id,
-                                                                 epoch,
-                                                                 built_display_list,
-                                                                 background_color,
-                                                                 viewport_size,
-                                                                 auxiliary_lists);
+                                // A frozen pipeline keeps its current display list.
+                                if let Err(err) = self.scene.set_root_display_list(pipeline_id,
+                                                                                   epoch,
+                                                                                   built_display_list,
+                                                                                   background_color,
+                                                                                   viewport_size,
+                                                                                   auxiliary_lists) {
+                                    warn!("Display list for pipeline {:?} epoch {:?} not set: {:?}",
+                                          pipeline_id, epoch, err);
+                                }
 
                                 self.build_scene();
                                 self.render()
@@ -225,6 +232,28 @@ impl RenderBackend {
                             self.publish_frame_and_notify_compositor(frame, &mut profile_counters);
                             frame_counter += 1;
                         }
+                        ApiMsg::SetCullRect(pipeline_id, cull_rect) => {
+                            self.scene.set_cull_rect(pipeline_id, cull_rect);
+                        }
+                        ApiMsg::FreezePipeline(pipeline_id, frozen) => {
+                            self.scene.freeze_pipeline(pipeline_id, frozen);
+                        }
+                        ApiMsg::TouchPipeline(pipeline_id, epoch) => {
+                            let frame = profile_counters.total_time.profile(|| {
+                                if let Err(err) = self.scene.touch_pipeline(pipeline_id, epoch) {
+                                    warn!("Pipeline {:?} not touched to epoch {:?}: {:?}",
+                                          pipeline_id, epoch, err);
+                                }
+
+                                self.build_scene();
+                                self.render()
+                            });
+
+                            if self.scene.root_pipeline_id.is_some() {
+                                self.publish_frame_and_notify_compositor(frame, &mut profile_counters);
+                                frame_counter += 1;
+                            }
+                        }
                         ApiMsg::Scroll(delta, cursor, move_phase) => {
                             let frame = profile_counters.total_time.profile(|| {
                                 if self.frame.scroll(delta, cursor, move_phase) {
@@ -275,6 +304,33 @@ impl RenderBackend {
                             tx.send(self.frame.get_scroll_layer_state())
                               .unwrap()
                         }
+                        ApiMsg::GetSceneMemoryReport(tx) => {
+                            let mut report = Vec::new();
+                            self.scene.report_memory(&mut |name, size| report.push((name.to_owned(), size)));
+                            tx.send(report).unwrap()
+                        }
+                        ApiMsg::GetPipelineOverflow(pipeline_id, tx) => {
+                            tx.send(self.scene.pipeline_overflow(pipeline_id)).unwrap()
+                        }
+                        ApiMsg::GetCommittedEpochs(tx) => {
+                            tx.send(self.scene.committed_epochs().into_iter().collect()).unwrap()
+                        }
+                        ApiMsg::GetSceneDot(pipeline_id, tx) => {
+                            tx.send(self.scene.to_dot(pipeline_id)).unwrap()
+                        }
+                        ApiMsg::GetItemsAt(pipeline_id, point, tx) => {
+                            tx.send(self.scene.items_at(pipeline_id, &point)).unwrap()
+                        }
+                        ApiMsg::GetDisplayItemCount(tx) => {
+                            tx.send(self.scene.total_item_count()).unwrap()
+                        }
+                        ApiMsg::GetDisplayItems(tx) => {
+                            let mut items = Vec::new();
+                            self.scene.iter_display_items(|pipeline_id, index, depth, item| {
+                                items.push((pipeline_id, index, depth, *item));
+                            });
+                            tx.send(items).unwrap()
+                        }
                         ApiMsg::RequestWebGLContext(size, attributes, tx) => {
                             if let Some(ref wrapper) = self.webrender_context_handle {
                                 let dispatcher: Option<Box<GLContextDispatcher>> = if cfg!(target_os = "windows") {
@@ -22,6 +22,7 @@ use internal_types::{BatchTextures, TextureSampler, GLContextHandleWrapper};
 use profiler::{Profiler, BackendProfileCounters};
 use profiler::{GpuProfileTag, RendererProfileTimers, RendererProfileCounters};
 use render_backend::RenderBackend;
+use scene::TransparentBackgroundPolicy;
 use std::cmp;
 use std::collections::HashMap;
 use std::f32;
@@ -594,6 +595,7 @@ impl Renderer {
         let (device_pixel_ratio, enable_aa) = (options.device_pixel_ratio, options.enable_aa);
         let payload_tx_for_backend = payload_tx.clone();
         let enable_recording = options.enable_recording;
+        let transparent_background_policy = options.transparent_background_policy;
         thread::spawn(move || {
             let mut backend = RenderBackend::new(api_rx,
                                                  payload_rx,
@@ -607,6 +609,7 @@ impl Renderer {
                                                  config,
                                                  debug,
                                                  enable_recording,
+                                                 transparent_background_policy,
                                                  backend_main_thread_dispatcher,
                                                  backend_vr_compositor);
             backend.run();
@@ -1402,6 +1405,12 @@ impl Renderer {
     pub fn set_profiler_enabled(&mut self, enabled: bool) {
         self.enable_profiler = enabled;
     }
+
+    /// Sets the color the framebuffer is cleared to where no root background
+    /// color is painted.
+    pub fn set_clear_color(&mut self, color: ColorF) {
+        self.clear_color = color;
+    }
 }
 
 pub enum ExternalImageSource {
@@ -1448,4 +1457,6 @@ pub struct RendererOptions {
     pub enable_subpixel_aa: bool,
     pub clear_framebuffer: bool,
     pub clear_color: ColorF,
+    /// Whether a fully transparent root background color is painted.
+    pub transparent_background_policy: TransparentBackgroundPolicy,
 }
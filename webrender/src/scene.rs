@@ -3,21 +3,25 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use fnv::FnvHasher;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
 use std::hash::BuildHasherDefault;
+use std::mem;
 use tiling::AuxiliaryListsMap;
 use webrender_traits::{AuxiliaryLists, BuiltDisplayList, PipelineId, Epoch, ColorF};
-use webrender_traits::{DisplayItem, SpecificDisplayItem, StackingContext};
-use webrender_traits::LayerSize;
+use webrender_traits::{ClipRegion, DisplayItem, SpecificDisplayItem, StackingContext};
+use webrender_traits::{LayerPoint, LayerRect, LayerSize};
 
 trait DisplayListHelpers {
-    fn starting_stacking_context<'a>(&'a self) -> Option<&'a StackingContext>;
+    fn starting_stacking_context<'a>(&'a self) -> Option<(&'a StackingContext, &'a ClipRegion)>;
 }
 
 impl DisplayListHelpers for Vec<DisplayItem> {
-    fn starting_stacking_context<'a>(&'a self) -> Option<&'a StackingContext> {
+    fn starting_stacking_context<'a>(&'a self) -> Option<(&'a StackingContext, &'a ClipRegion)> {
         self.first().and_then(|item| match item.item {
-            SpecificDisplayItem::PushStackingContext(ref item) => Some(&item.stacking_context),
+            SpecificDisplayItem::PushStackingContext(ref specific_item) => {
+                Some((&specific_item.stacking_context, &item.clip))
+            },
             _ => None,
         })
     }
@@ -30,6 +34,67 @@ pub struct ScenePipeline {
     pub epoch: Epoch,
     pub viewport_size: LayerSize,
     pub background_color: Option<ColorF>,
+    /// The rect outside of which the embedder expects items to be invisible.
+    /// This is only recorded here; culling happens later.
+    pub cull_rect: Option<LayerRect>,
+}
+
+/// The structural differences between two scenes, from `Scene::diff`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SceneDiff {
+    /// Pipelines only present in the newer scene.
+    pub added_pipelines: Vec<PipelineId>,
+    /// Pipelines only present in the older scene.
+    pub removed_pipelines: Vec<PipelineId>,
+    /// Pipelines present in both scenes with a different epoch.
+    pub updated_pipelines: Vec<PipelineId>,
+    /// Pipelines present in both scenes whose display list items differ.
+    pub changed_display_lists: Vec<PipelineId>,
+}
+
+/// A problem found in a pipeline's display list by `Scene::validate_display_list`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisplayListProblem {
+    /// The pipeline's display list has no items.
+    Empty(PipelineId),
+    /// The item at this index draws content but has a zero-area rect.
+    ZeroAreaItem(PipelineId, usize),
+    /// The item at this index has a NaN or infinite rect or clip coordinate.
+    NonFiniteItem(PipelineId, usize),
+    /// The iframe at this index refers to a pipeline that isn't in the scene.
+    UnknownIframePipeline(PipelineId, usize, PipelineId),
+}
+
+/// Controls whether a fully transparent root background color is painted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransparentBackgroundPolicy {
+    /// Don't paint a background for a zero-alpha color.
+    Skip,
+    /// Paint the given color in place of a zero-alpha color, e.g. when
+    /// compositing onto a known backdrop.
+    Fill(ColorF),
+}
+
+/// An error returned when a scene update is rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SceneError {
+    /// The pipeline was frozen with `Scene::freeze_pipeline`.
+    PipelineFrozen(PipelineId),
+    /// The pipeline isn't in the scene.
+    UnknownPipeline(PipelineId),
+    /// The new epoch isn't greater than the pipeline's current epoch, which
+    /// is given.
+    StaleEpoch(PipelineId, Epoch),
+    /// A pipeline with this id is already in the scene.
+    DuplicatePipeline(PipelineId),
+}
+
+/// A lightweight record of the pipelines and epochs in a scene, used to check
+/// that an operation left the scene unchanged. No resource state is captured.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneSnapshot {
+    pub root_pipeline_id: Option<PipelineId>,
+    pub pipelines: Vec<(PipelineId, Epoch, usize)>,
 }
 
 /// A complete representation of the layout bundling visible pipelines together.
@@ -39,6 +104,12 @@ pub struct Scene {
     pub pipeline_sizes: HashMap<PipelineId, LayerSize>,
     pub pipeline_auxiliary_lists: AuxiliaryListsMap,
     pub display_lists: HashMap<PipelineId, Vec<DisplayItem>, BuildHasherDefault<FnvHasher>>,
+    pub transparent_background_policy: TransparentBackgroundPolicy,
+    frozen_pipelines: HashSet<PipelineId, BuildHasherDefault<FnvHasher>>,
+    /// The most recent epochs committed for each pipeline, oldest first,
+    /// when `epoch_history_length` is non-zero.
+    epoch_history: HashMap<PipelineId, VecDeque<Epoch>, BuildHasherDefault<FnvHasher>>,
+    epoch_history_length: usize,
 }
 
 impl Scene {
@@ -49,9 +120,171 @@ impl Scene {
             pipeline_map: HashMap::with_hasher(Default::default()),
             pipeline_auxiliary_lists: HashMap::with_hasher(Default::default()),
             display_lists: HashMap::with_hasher(Default::default()),
+            transparent_background_policy: TransparentBackgroundPolicy::Skip,
+            frozen_pipelines: HashSet::with_hasher(Default::default()),
+            epoch_history: HashMap::with_hasher(Default::default()),
+            epoch_history_length: 0,
+        }
+    }
+
+    /// Returns the overflow rect of a pipeline's root stacking context, which
+    /// is the clip region it was pushed with.
+    pub fn pipeline_overflow(&self, pipeline_id: PipelineId) -> Option<LayerRect> {
+        self.display_lists.get(&pipeline_id)
+            .and_then(|display_list| display_list.starting_stacking_context())
+            .map(|(_, clip)| clip.main)
+    }
+
+    /// Returns the epoch most recently committed for each pipeline.
+    pub fn committed_epochs(&self) -> HashMap<PipelineId, Epoch, BuildHasherDefault<FnvHasher>> {
+        self.pipeline_map.iter().map(|(pipeline_id, pipeline)| (*pipeline_id, pipeline.epoch)).collect()
+    }
+
+    /// Returns the background color most recently committed for a pipeline,
+    /// as requested by the embedder.
+    pub fn get_background_color(&self, pipeline_id: PipelineId) -> Option<ColorF> {
+        self.pipeline_map.get(&pipeline_id).and_then(|pipeline| pipeline.background_color)
+    }
+
+    /// Returns the background color that should be painted for a pipeline,
+    /// applying the transparent background policy to zero-alpha colors.
+    pub fn resolved_background_color(&self, pipeline: &ScenePipeline) -> Option<ColorF> {
+        pipeline.background_color.and_then(|color| {
+            if color.a > 0.0 {
+                return Some(color);
+            }
+            match self.transparent_background_policy {
+                TransparentBackgroundPolicy::Skip => None,
+                TransparentBackgroundPolicy::Fill(fill_color) => Some(fill_color),
+            }
+        })
+    }
+
+    /// Freezes or unfreezes a pipeline. Display list updates for a frozen
+    /// pipeline are rejected, so it keeps showing its current content while
+    /// other pipelines update.
+    pub fn freeze_pipeline(&mut self, pipeline_id: PipelineId, frozen: bool) {
+        if frozen {
+            self.frozen_pipelines.insert(pipeline_id);
+        } else {
+            self.frozen_pipelines.remove(&pipeline_id);
         }
     }
 
+    pub fn is_pipeline_frozen(&self, pipeline_id: PipelineId) -> bool {
+        self.frozen_pipelines.contains(&pipeline_id)
+    }
+
+    /// Sets how many of the most recent epochs committed for each pipeline
+    /// are recorded for `epoch_history`. Zero, the default, turns recording
+    /// off and drops any recorded history.
+    pub fn set_epoch_history_length(&mut self, length: usize) {
+        self.epoch_history_length = length;
+        if length == 0 {
+            self.epoch_history.clear();
+            return;
+        }
+        for history in self.epoch_history.values_mut() {
+            while history.len() > length {
+                history.pop_front();
+            }
+        }
+    }
+
+    /// Returns the recorded epochs committed for a pipeline, oldest first,
+    /// including epochs advanced with `touch_pipeline`.
+    pub fn epoch_history(&self, pipeline_id: PipelineId) -> Vec<Epoch> {
+        self.epoch_history.get(&pipeline_id).map_or(Vec::new(), |history| history.iter().cloned().collect())
+    }
+
+    fn record_epoch(&mut self, pipeline_id: PipelineId, epoch: Epoch) {
+        if self.epoch_history_length == 0 {
+            return;
+        }
+        let history = self.epoch_history.entry(pipeline_id).or_insert(VecDeque::new());
+        if history.len() == self.epoch_history_length {
+            history.pop_front();
+        }
+        history.push_back(epoch);
+    }
+
+    /// Sets the culling rect of a pipeline that's in the scene. Pipelines
+    /// that haven't been given a display list yet are ignored.
+    pub fn set_cull_rect(&mut self, pipeline_id: PipelineId, cull_rect: Option<LayerRect>) {
+        if let Some(pipeline) = self.pipeline_map.get_mut(&pipeline_id) {
+            pipeline.cull_rect = cull_rect;
+        }
+    }
+
+    pub fn cull_rect(&self, pipeline_id: PipelineId) -> Option<LayerRect> {
+        self.pipeline_map.get(&pipeline_id).and_then(|pipeline| pipeline.cull_rect)
+    }
+
+    /// Changes the id of a pipeline, e.g. to avoid a collision when replaying
+    /// a captured scene. Every reference to the pipeline is updated: the
+    /// scene's maps, the root pipeline id, and iframes in all display lists.
+    pub fn rename_pipeline(&mut self, old_id: PipelineId, new_id: PipelineId) -> Result<(), SceneError> {
+        if !self.pipeline_map.contains_key(&old_id) {
+            return Err(SceneError::UnknownPipeline(old_id));
+        }
+        if old_id == new_id {
+            return Ok(());
+        }
+        if self.pipeline_map.contains_key(&new_id) || self.display_lists.contains_key(&new_id) {
+            return Err(SceneError::DuplicatePipeline(new_id));
+        }
+
+        let mut pipeline = self.pipeline_map.remove(&old_id).unwrap();
+        pipeline.pipeline_id = new_id;
+        self.pipeline_map.insert(new_id, pipeline);
+        if let Some(size) = self.pipeline_sizes.remove(&old_id) {
+            self.pipeline_sizes.insert(new_id, size);
+        }
+        if let Some(auxiliary_lists) = self.pipeline_auxiliary_lists.remove(&old_id) {
+            self.pipeline_auxiliary_lists.insert(new_id, auxiliary_lists);
+        }
+        if let Some(items) = self.display_lists.remove(&old_id) {
+            self.display_lists.insert(new_id, items);
+        }
+        if self.frozen_pipelines.remove(&old_id) {
+            self.frozen_pipelines.insert(new_id);
+        }
+        if let Some(history) = self.epoch_history.remove(&old_id) {
+            self.epoch_history.insert(new_id, history);
+        }
+        if self.root_pipeline_id == Some(old_id) {
+            self.root_pipeline_id = Some(new_id);
+        }
+
+        for items in self.display_lists.values_mut() {
+            for item in items.iter_mut() {
+                if let SpecificDisplayItem::Iframe(ref mut iframe) = item.item {
+                    if iframe.pipeline_id == old_id {
+                        iframe.pipeline_id = new_id;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Advances a pipeline's epoch without changing its display list, so
+    /// the embedder can force a recomposite.
+    pub fn touch_pipeline(&mut self, pipeline_id: PipelineId, new_epoch: Epoch) -> Result<(), SceneError> {
+        {
+            let pipeline = match self.pipeline_map.get_mut(&pipeline_id) {
+                Some(pipeline) => pipeline,
+                None => return Err(SceneError::UnknownPipeline(pipeline_id)),
+            };
+            if new_epoch <= pipeline.epoch {
+                return Err(SceneError::StaleEpoch(pipeline_id, pipeline.epoch));
+            }
+            pipeline.epoch = new_epoch;
+        }
+        self.record_epoch(pipeline_id, new_epoch);
+        Ok(())
+    }
+
     pub fn set_root_pipeline_id(&mut self, pipeline_id: PipelineId) {
         self.root_pipeline_id = Some(pipeline_id);
     }
@@ -62,17 +295,948 @@ impl Scene {
                                  built_display_list: BuiltDisplayList,
                                  background_color: Option<ColorF>,
                                  viewport_size: LayerSize,
-                                 auxiliary_lists: AuxiliaryLists) {
+                                 auxiliary_lists: AuxiliaryLists)
+                                 -> Result<(), SceneError> {
+        if self.is_pipeline_frozen(pipeline_id) {
+            return Err(SceneError::PipelineFrozen(pipeline_id));
+        }
+
+        let items = built_display_list.all_display_items();
+        debug_assert_balanced(pipeline_id, items);
+
         self.pipeline_auxiliary_lists.insert(pipeline_id, auxiliary_lists);
-        self.display_lists.insert(pipeline_id, built_display_list.all_display_items().to_vec());
+        self.display_lists.insert(pipeline_id, items.to_vec());
 
+        // The cull rect is set separately, so it survives display list updates.
+        let cull_rect = self.cull_rect(pipeline_id);
         let new_pipeline = ScenePipeline {
             pipeline_id: pipeline_id,
             epoch: epoch,
             viewport_size: viewport_size,
             background_color: background_color,
+            cull_rect: cull_rect,
         };
 
         self.pipeline_map.insert(pipeline_id, new_pipeline);
+        self.record_epoch(pipeline_id, epoch);
+        Ok(())
+    }
+
+    /// Calls `f` for every display item in the scene with the owning pipeline,
+    /// the index of the item in that pipeline's display list, and the
+    /// stacking context nesting depth the item sits at.
+    pub fn iter_display_items<F>(&self, mut f: F)
+        where F: FnMut(PipelineId, usize, usize, &DisplayItem) {
+        for (pipeline_id, items) in &self.display_lists {
+            let mut depth: usize = 0;
+            for (index, item) in items.iter().enumerate() {
+                if let SpecificDisplayItem::PopStackingContext = item.item {
+                    depth = depth.saturating_sub(1);
+                }
+                f(*pipeline_id, index, depth, item);
+                if let SpecificDisplayItem::PushStackingContext(..) = item.item {
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Checks every display list for obviously invalid items, to catch
+    /// malformed builders before they reach the renderer.
+    pub fn validate_display_list(&self) -> Vec<DisplayListProblem> {
+        let mut problems = Vec::new();
+
+        for (pipeline_id, items) in &self.display_lists {
+            if items.is_empty() {
+                problems.push(DisplayListProblem::Empty(*pipeline_id));
+            }
+
+            for (index, item) in items.iter().enumerate() {
+                let rects = [item.rect, item.clip.main];
+                let is_finite = rects.iter().all(|rect| {
+                    rect.origin.x.is_finite() && rect.origin.y.is_finite() &&
+                    rect.size.width.is_finite() && rect.size.height.is_finite()
+                });
+                if !is_finite {
+                    problems.push(DisplayListProblem::NonFiniteItem(*pipeline_id, index));
+                }
+
+                match item.item {
+                    SpecificDisplayItem::PushStackingContext(..) |
+                    SpecificDisplayItem::PopStackingContext |
+                    SpecificDisplayItem::PushScrollLayer(..) |
+                    SpecificDisplayItem::PopScrollLayer => continue,
+                    SpecificDisplayItem::Iframe(ref iframe) => {
+                        if !self.pipeline_map.contains_key(&iframe.pipeline_id) {
+                            problems.push(DisplayListProblem::UnknownIframePipeline(*pipeline_id,
+                                                                                    index,
+                                                                                    iframe.pipeline_id));
+                        }
+                    }
+                    _ => {}
+                }
+
+                if is_finite && item.rect.size.width * item.rect.size.height == 0.0 {
+                    problems.push(DisplayListProblem::ZeroAreaItem(*pipeline_id, index));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Describes how `other` differs from this scene, treating `other` as the
+    /// newer of the two. Display lists are compared item by item; auxiliary
+    /// list contents aren't compared.
+    pub fn diff(&self, other: &Scene) -> SceneDiff {
+        let mut diff = SceneDiff::default();
+
+        for (pipeline_id, pipeline) in &self.pipeline_map {
+            match other.pipeline_map.get(pipeline_id) {
+                None => diff.removed_pipelines.push(*pipeline_id),
+                Some(other_pipeline) => {
+                    if other_pipeline.epoch != pipeline.epoch {
+                        diff.updated_pipelines.push(*pipeline_id);
+                    }
+                    if self.display_lists.get(pipeline_id) != other.display_lists.get(pipeline_id) {
+                        diff.changed_display_lists.push(*pipeline_id);
+                    }
+                }
+            }
+        }
+
+        for pipeline_id in other.pipeline_map.keys() {
+            if !self.pipeline_map.contains_key(pipeline_id) {
+                diff.added_pipelines.push(*pipeline_id);
+            }
+        }
+
+        diff
+    }
+
+    /// Returns the number of display items across all pipelines. Cheap enough
+    /// to call every frame to spot runaway display lists.
+    pub fn total_item_count(&self) -> usize {
+        self.display_lists.values().map(|items| items.len()).sum()
+    }
+
+    /// Returns the indices, in paint order, of the pipeline's drawing items
+    /// whose bounds contain `point`. This is a debugging aid rather than a
+    /// hit test: each item's rect is compared as stored, in its stacking
+    /// context's coordinates, and clips and transforms are ignored.
+    pub fn items_at(&self, pipeline_id: PipelineId, point: &LayerPoint) -> Vec<usize> {
+        let items = match self.display_lists.get(&pipeline_id) {
+            Some(items) => items,
+            None => return Vec::new(),
+        };
+        items.iter()
+             .enumerate()
+             .filter(|&(_, item)| is_drawing_item(item) && item.rect.contains(point))
+             .map(|(index, _)| index)
+             .collect()
+    }
+
+    /// Releases excess capacity held by the scene's maps, e.g. after going
+    /// from a heavy page to a light one. The contents are left unchanged.
+    pub fn shrink_to_fit(&mut self) {
+        self.pipeline_map.shrink_to_fit();
+        self.pipeline_sizes.shrink_to_fit();
+        self.pipeline_auxiliary_lists.shrink_to_fit();
+        self.display_lists.shrink_to_fit();
+    }
+
+    /// Reports the approximate heap memory used by the scene, in bytes, by
+    /// calling `op` once per map with its name. Map sizes count the key and
+    /// value of every allocated slot but not the hash table's own overhead,
+    /// so they're underestimates. The display lists and auxiliary lists
+    /// include the buffers they own.
+    pub fn report_memory(&self, op: &mut FnMut(&str, usize)) {
+        fn map_size<K, V>(capacity: usize) -> usize {
+            capacity * (mem::size_of::<K>() + mem::size_of::<V>())
+        }
+
+        op("pipeline_map", map_size::<PipelineId, ScenePipeline>(self.pipeline_map.capacity()));
+        op("pipeline_sizes", map_size::<PipelineId, LayerSize>(self.pipeline_sizes.capacity()));
+        op("pipeline_auxiliary_lists",
+           map_size::<PipelineId, AuxiliaryLists>(self.pipeline_auxiliary_lists.capacity()) +
+           self.pipeline_auxiliary_lists.values().map(|lists| lists.data().len()).sum::<usize>());
+        op("display_lists",
+           map_size::<PipelineId, Vec<DisplayItem>>(self.display_lists.capacity()) +
+           self.display_lists.values()
+               .map(|items| items.capacity() * mem::size_of::<DisplayItem>())
+               .sum::<usize>());
+        op("frozen_pipelines", map_size::<PipelineId, ()>(self.frozen_pipelines.capacity()));
+        op("epoch_history",
+           map_size::<PipelineId, VecDeque<Epoch>>(self.epoch_history.capacity()) +
+           self.epoch_history.values()
+               .map(|history| history.capacity() * mem::size_of::<Epoch>())
+               .sum::<usize>());
+    }
+
+    /// Captures the pipeline ids, epochs and display list lengths of this scene.
+    pub fn snapshot(&self) -> SceneSnapshot {
+        let mut pipelines: Vec<(PipelineId, Epoch, usize)> = self.pipeline_map.values().map(|pipeline| {
+            let item_count = self.display_lists.get(&pipeline.pipeline_id).map_or(0, |items| items.len());
+            (pipeline.pipeline_id, pipeline.epoch, item_count)
+        }).collect();
+        pipelines.sort_by_key(|&(pipeline_id, _, _)| (pipeline_id.0, pipeline_id.1));
+
+        SceneSnapshot {
+            root_pipeline_id: self.root_pipeline_id,
+            pipelines: pipelines,
+        }
+    }
+
+    /// Returns true if the scene still matches a snapshot taken earlier.
+    pub fn matches_snapshot(&self, snapshot: &SceneSnapshot) -> bool {
+        self.snapshot() == *snapshot
+    }
+
+    /// Calls `op` with each stacking context reachable from a pipeline in
+    /// preorder, along with the pipeline it's in and its nesting depth, the
+    /// pipeline's root being at depth 0. Iframes continue into the pipeline
+    /// they show, whose root is one level deeper than the iframe. An iframe
+    /// of a pipeline that's already being visited above it is skipped.
+    pub fn visit_stacking_contexts(&self,
+                                   pipeline_id: PipelineId,
+                                   op: &mut FnMut(PipelineId, &StackingContext, usize)) {
+        self.visit_pipeline_stacking_contexts(pipeline_id, 0, op, &mut Vec::new());
+    }
+
+    fn visit_pipeline_stacking_contexts(&self,
+                                        pipeline_id: PipelineId,
+                                        base_depth: usize,
+                                        op: &mut FnMut(PipelineId, &StackingContext, usize),
+                                        ancestors: &mut Vec<PipelineId>) {
+        let items = match self.display_lists.get(&pipeline_id) {
+            Some(items) => items,
+            None => return,
+        };
+        ancestors.push(pipeline_id);
+
+        let mut depth = base_depth;
+        for item in items {
+            match item.item {
+                SpecificDisplayItem::PushStackingContext(ref item) => {
+                    op(pipeline_id, &item.stacking_context, depth);
+                    depth += 1;
+                }
+                SpecificDisplayItem::PopStackingContext => {
+                    if depth > base_depth {
+                        depth -= 1;
+                    }
+                }
+                SpecificDisplayItem::Iframe(ref iframe) if !ancestors.contains(&iframe.pipeline_id) => {
+                    self.visit_pipeline_stacking_contexts(iframe.pipeline_id, depth, op, ancestors);
+                }
+                _ => {}
+            }
+        }
+
+        ancestors.pop();
+    }
+
+    /// Describes the stacking context tree reachable from a pipeline in
+    /// Graphviz dot format. Runs of drawing items are collapsed into a single
+    /// leaf, and iframes lead to the root of the pipeline they show. An
+    /// iframe of a pipeline that's already being shown above it is drawn as a
+    /// dashed back-edge instead of being followed.
+    pub fn to_dot(&self, pipeline_id: PipelineId) -> String {
+        let mut dot = String::from("digraph scene {\n");
+        let mut next_node = 0;
+        self.write_dot_pipeline(pipeline_id, &mut dot, &mut next_node, &mut Vec::new());
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn write_dot_pipeline(&self,
+                          pipeline_id: PipelineId,
+                          dot: &mut String,
+                          next_node: &mut usize,
+                          ancestors: &mut Vec<(PipelineId, usize)>)
+                          -> usize {
+        let pipeline_node = *next_node;
+        *next_node += 1;
+        let items = match self.display_lists.get(&pipeline_id) {
+            Some(items) => items,
+            None => {
+                writeln!(dot, "  n{} [label=\"pipeline {:?} (missing)\" shape=box];",
+                         pipeline_node, pipeline_id).unwrap();
+                return pipeline_node;
+            }
+        };
+        writeln!(dot, "  n{} [label=\"pipeline {:?}\" shape=box];", pipeline_node, pipeline_id).unwrap();
+        ancestors.push((pipeline_id, pipeline_node));
+
+        let mut parents = vec![pipeline_node];
+        let mut pending_items = 0;
+        for item in items {
+            let parent = *parents.last().unwrap();
+            if pending_items > 0 && !is_drawing_item(item) {
+                writeln!(dot, "  n{} [label=\"{} items\" shape=note];", *next_node, pending_items).unwrap();
+                writeln!(dot, "  n{} -> n{};", parent, *next_node).unwrap();
+                *next_node += 1;
+                pending_items = 0;
+            }
+
+            match item.item {
+                SpecificDisplayItem::PushStackingContext(..) |
+                SpecificDisplayItem::PushScrollLayer(..) => {
+                    let kind = match item.item {
+                        SpecificDisplayItem::PushStackingContext(..) => "stacking context",
+                        _ => "scroll layer",
+                    };
+                    writeln!(dot, "  n{} [label=\"{} {:?}\"];", *next_node, kind, item.rect).unwrap();
+                    writeln!(dot, "  n{} -> n{};", parent, *next_node).unwrap();
+                    parents.push(*next_node);
+                    *next_node += 1;
+                }
+                SpecificDisplayItem::PopStackingContext |
+                SpecificDisplayItem::PopScrollLayer => {
+                    if parents.len() > 1 {
+                        parents.pop();
+                    }
+                }
+                SpecificDisplayItem::Iframe(ref iframe) => {
+                    let iframe_node = *next_node;
+                    *next_node += 1;
+                    writeln!(dot, "  n{} [label=\"iframe {:?}\"];", iframe_node, item.rect).unwrap();
+                    writeln!(dot, "  n{} -> n{};", parent, iframe_node).unwrap();
+                    match ancestors.iter().find(|&&(ancestor, _)| ancestor == iframe.pipeline_id) {
+                        Some(&(_, ancestor_node)) => {
+                            writeln!(dot, "  n{} -> n{} [style=dashed label=\"back edge\"];",
+                                     iframe_node, ancestor_node).unwrap();
+                        }
+                        None => {
+                            let child_node = self.write_dot_pipeline(iframe.pipeline_id, dot, next_node, ancestors);
+                            writeln!(dot, "  n{} -> n{};", iframe_node, child_node).unwrap();
+                        }
+                    }
+                }
+                _ => pending_items += 1,
+            }
+        }
+        if pending_items > 0 {
+            writeln!(dot, "  n{} [label=\"{} items\" shape=note];", *next_node, pending_items).unwrap();
+            writeln!(dot, "  n{} -> n{};", *parents.last().unwrap(), *next_node).unwrap();
+            *next_node += 1;
+        }
+
+        ancestors.pop();
+        pipeline_node
+    }
+}
+
+/// In debug builds, panics if the stacking context and scroll layer pushes
+/// and pops in a display list don't nest properly, naming the first item
+/// that breaks the nesting.
+fn debug_assert_balanced(pipeline_id: PipelineId, items: &[DisplayItem]) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    let mut open = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        match item.item {
+            SpecificDisplayItem::PushStackingContext(..) => open.push("stacking context"),
+            SpecificDisplayItem::PushScrollLayer(..) => open.push("scroll layer"),
+            SpecificDisplayItem::PopStackingContext |
+            SpecificDisplayItem::PopScrollLayer => {
+                let kind = match item.item {
+                    SpecificDisplayItem::PopStackingContext => "stacking context",
+                    _ => "scroll layer",
+                };
+                match open.pop() {
+                    Some(open_kind) if open_kind == kind => {}
+                    Some(open_kind) => {
+                        panic!("display list for {:?} pops a {} at item {} while a {} is open",
+                               pipeline_id, kind, index, open_kind);
+                    }
+                    None => {
+                        panic!("display list for {:?} pops a {} at item {} with nothing open",
+                               pipeline_id, kind, index);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(open_kind) = open.pop() {
+        panic!("display list for {:?} ends with {} unclosed, the innermost a {}",
+               pipeline_id, open.len() + 1, open_kind);
+    }
+}
+
+fn is_drawing_item(item: &DisplayItem) -> bool {
+    match item.item {
+        SpecificDisplayItem::PushStackingContext(..) |
+        SpecificDisplayItem::PopStackingContext |
+        SpecificDisplayItem::PushScrollLayer(..) |
+        SpecificDisplayItem::PopScrollLayer |
+        SpecificDisplayItem::Iframe(..) => false,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DisplayListProblem, Scene, SceneDiff, SceneError, TransparentBackgroundPolicy};
+    use super::debug_assert_balanced;
+    use webrender_traits::{ColorF, DisplayListBuilder, Epoch, LayerPoint, LayerRect, LayerSize};
+    use webrender_traits::{LayoutTransform, MixBlendMode, PipelineId, ScrollPolicy, ServoScrollRootId};
+    use webrender_traits::SpecificDisplayItem;
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> LayerRect {
+        LayerRect::new(LayerPoint::new(x, y), LayerSize::new(width, height))
+    }
+
+    fn push_stacking_context(builder: &mut DisplayListBuilder, bounds: LayerRect) {
+        let clip = builder.new_clip_region(&bounds, Vec::new(), None);
+        builder.push_stacking_context(ScrollPolicy::Scrollable,
+                                      bounds,
+                                      clip,
+                                      0,
+                                      &LayoutTransform::identity(),
+                                      &LayoutTransform::identity(),
+                                      MixBlendMode::Normal,
+                                      Vec::new());
+    }
+
+    fn push_rect(builder: &mut DisplayListBuilder, bounds: LayerRect) {
+        let clip = builder.new_clip_region(&bounds, Vec::new(), None);
+        builder.push_rect(bounds, clip, ColorF::new(1.0, 0.0, 0.0, 1.0));
+    }
+
+    fn push_iframe(builder: &mut DisplayListBuilder, bounds: LayerRect, pipeline_id: PipelineId) {
+        let clip = builder.new_clip_region(&bounds, Vec::new(), None);
+        builder.push_iframe(bounds, clip, pipeline_id);
+    }
+
+    /// A stacking context holding a single rect.
+    fn simple_builder(pipeline_id: PipelineId) -> DisplayListBuilder {
+        let mut builder = DisplayListBuilder::new(pipeline_id);
+        push_stacking_context(&mut builder, rect(0.0, 0.0, 100.0, 100.0));
+        push_rect(&mut builder, rect(10.0, 10.0, 20.0, 20.0));
+        builder.pop_stacking_context();
+        builder
+    }
+
+    fn set_display_list(scene: &mut Scene,
+                        builder: DisplayListBuilder,
+                        epoch: u32,
+                        background_color: Option<ColorF>)
+                        -> Result<(), SceneError> {
+        let pipeline_id = builder.pipeline_id;
+        let (display_list, auxiliary_lists) = builder.finalize();
+        scene.set_root_display_list(pipeline_id,
+                                    Epoch(epoch),
+                                    display_list,
+                                    background_color,
+                                    LayerSize::new(100.0, 100.0),
+                                    auxiliary_lists)
+    }
+
+    fn scene_with(pipeline_ids: &[PipelineId]) -> Scene {
+        let mut scene = Scene::new();
+        for pipeline_id in pipeline_ids {
+            set_display_list(&mut scene, simple_builder(*pipeline_id), 1, None).unwrap();
+        }
+        scene
+    }
+
+    #[test]
+    fn transparent_background_is_skipped_by_default() {
+        let mut scene = Scene::new();
+        let transparent = ColorF::new(1.0, 1.0, 1.0, 0.0);
+        set_display_list(&mut scene, simple_builder(PipelineId(0, 0)), 1, Some(transparent)).unwrap();
+        let opaque = ColorF::new(0.0, 0.0, 1.0, 1.0);
+        set_display_list(&mut scene, simple_builder(PipelineId(0, 1)), 1, Some(opaque)).unwrap();
+        set_display_list(&mut scene, simple_builder(PipelineId(0, 2)), 1, None).unwrap();
+
+        assert_eq!(scene.resolved_background_color(&scene.pipeline_map[&PipelineId(0, 0)]), None);
+        assert_eq!(scene.resolved_background_color(&scene.pipeline_map[&PipelineId(0, 1)]), Some(opaque));
+        assert_eq!(scene.resolved_background_color(&scene.pipeline_map[&PipelineId(0, 2)]), None);
+        // the committed color is reported as given
+        assert_eq!(scene.get_background_color(PipelineId(0, 0)), Some(transparent));
+    }
+
+    #[test]
+    fn transparent_background_is_filled_by_policy() {
+        let mut scene = Scene::new();
+        let fill = ColorF::new(0.0, 1.0, 0.0, 1.0);
+        scene.transparent_background_policy = TransparentBackgroundPolicy::Fill(fill);
+        let transparent = ColorF::new(1.0, 1.0, 1.0, 0.0);
+        set_display_list(&mut scene, simple_builder(PipelineId(0, 0)), 1, Some(transparent)).unwrap();
+        let opaque = ColorF::new(0.0, 0.0, 1.0, 1.0);
+        set_display_list(&mut scene, simple_builder(PipelineId(0, 1)), 1, Some(opaque)).unwrap();
+        set_display_list(&mut scene, simple_builder(PipelineId(0, 2)), 1, None).unwrap();
+
+        assert_eq!(scene.resolved_background_color(&scene.pipeline_map[&PipelineId(0, 0)]), Some(fill));
+        assert_eq!(scene.resolved_background_color(&scene.pipeline_map[&PipelineId(0, 1)]), Some(opaque));
+        // a pipeline without a background color still doesn't get one
+        assert_eq!(scene.resolved_background_color(&scene.pipeline_map[&PipelineId(0, 2)]), None);
+    }
+
+    #[test]
+    fn validate_accepts_well_formed_lists() {
+        let scene = scene_with(&[PipelineId(0, 0)]);
+        assert_eq!(scene.validate_display_list(), Vec::new());
+    }
+
+    #[test]
+    fn validate_reports_empty_lists() {
+        let mut scene = Scene::new();
+        set_display_list(&mut scene, DisplayListBuilder::new(PipelineId(0, 0)), 1, None).unwrap();
+        assert_eq!(scene.validate_display_list(), vec![DisplayListProblem::Empty(PipelineId(0, 0))]);
+    }
+
+    #[test]
+    fn validate_reports_bad_items() {
+        let pipeline_id = PipelineId(0, 0);
+        let mut builder = DisplayListBuilder::new(pipeline_id);
+        push_stacking_context(&mut builder, rect(0.0, 0.0, 100.0, 100.0));
+        push_rect(&mut builder, rect(10.0, 10.0, 0.0, 20.0));
+        push_rect(&mut builder, rect(10.0, ::std::f32::NAN, 20.0, 20.0));
+        push_iframe(&mut builder, rect(0.0, 0.0, 50.0, 50.0), PipelineId(0, 7));
+        builder.pop_stacking_context();
+        let mut scene = Scene::new();
+        set_display_list(&mut scene, builder, 1, None).unwrap();
+
+        // stacking context pushes and pops have no rect, so they're not zero-area items
+        assert_eq!(scene.validate_display_list(),
+                   vec![DisplayListProblem::ZeroAreaItem(pipeline_id, 1),
+                        DisplayListProblem::NonFiniteItem(pipeline_id, 2),
+                        DisplayListProblem::UnknownIframePipeline(pipeline_id, 3, PipelineId(0, 7))]);
+    }
+
+    #[test]
+    fn diff_of_identical_scenes_is_empty() {
+        let pipeline_ids = [PipelineId(0, 0), PipelineId(0, 1)];
+        assert_eq!(scene_with(&pipeline_ids).diff(&scene_with(&pipeline_ids)), SceneDiff::default());
+        assert_eq!(Scene::new().diff(&Scene::new()), SceneDiff::default());
+    }
+
+    #[test]
+    fn diff_reports_added_and_removed_pipelines() {
+        let old_scene = scene_with(&[PipelineId(0, 0), PipelineId(0, 1)]);
+        let new_scene = scene_with(&[PipelineId(0, 0), PipelineId(0, 2)]);
+        let diff = old_scene.diff(&new_scene);
+        assert_eq!(diff.added_pipelines, vec![PipelineId(0, 2)]);
+        assert_eq!(diff.removed_pipelines, vec![PipelineId(0, 1)]);
+        assert!(diff.updated_pipelines.is_empty());
+        assert!(diff.changed_display_lists.is_empty());
+
+        // against an empty scene, everything is added or removed
+        assert_eq!(Scene::new().diff(&old_scene).added_pipelines.len(), 2);
+        assert_eq!(old_scene.diff(&Scene::new()).removed_pipelines.len(), 2);
+    }
+
+    #[test]
+    fn diff_reports_epoch_and_display_list_changes() {
+        let pipeline_id = PipelineId(0, 0);
+        let old_scene = scene_with(&[pipeline_id]);
+
+        let mut new_scene = Scene::new();
+        set_display_list(&mut new_scene, simple_builder(pipeline_id), 2, None).unwrap();
+        assert_eq!(old_scene.diff(&new_scene),
+                   SceneDiff { updated_pipelines: vec![pipeline_id], ..SceneDiff::default() });
+
+        let mut builder = simple_builder(pipeline_id);
+        push_rect(&mut builder, rect(50.0, 50.0, 10.0, 10.0));
+        let mut new_scene = Scene::new();
+        set_display_list(&mut new_scene, builder, 1, None).unwrap();
+        assert_eq!(old_scene.diff(&new_scene),
+                   SceneDiff { changed_display_lists: vec![pipeline_id], ..SceneDiff::default() });
+    }
+
+    #[test]
+    fn frozen_pipeline_rejects_display_lists() {
+        let pipeline_id = PipelineId(0, 0);
+        let mut scene = scene_with(&[pipeline_id]);
+        scene.freeze_pipeline(pipeline_id, true);
+        assert!(scene.is_pipeline_frozen(pipeline_id));
+
+        let snapshot = scene.snapshot();
+        assert_eq!(set_display_list(&mut scene, DisplayListBuilder::new(pipeline_id), 2, None),
+                   Err(SceneError::PipelineFrozen(pipeline_id)));
+        assert!(scene.matches_snapshot(&snapshot));
+
+        // other pipelines still update
+        assert_eq!(set_display_list(&mut scene, simple_builder(PipelineId(0, 1)), 1, None), Ok(()));
+
+        scene.freeze_pipeline(pipeline_id, false);
+        assert!(!scene.is_pipeline_frozen(pipeline_id));
+        assert_eq!(set_display_list(&mut scene, DisplayListBuilder::new(pipeline_id), 2, None), Ok(()));
+        assert_eq!(scene.pipeline_map[&pipeline_id].epoch, Epoch(2));
+        assert!(scene.display_lists[&pipeline_id].is_empty());
+    }
+
+    #[test]
+    fn balanced_display_lists_pass() {
+        let mut builder = DisplayListBuilder::new(PipelineId(0, 0));
+        push_stacking_context(&mut builder, rect(0.0, 0.0, 100.0, 100.0));
+        builder.push_scroll_layer(rect(0.0, 0.0, 50.0, 50.0), LayerSize::new(100.0, 100.0), ServoScrollRootId(0));
+        push_stacking_context(&mut builder, rect(0.0, 0.0, 10.0, 10.0));
+        builder.pop_stacking_context();
+        builder.pop_scroll_layer();
+        builder.pop_stacking_context();
+        debug_assert_balanced(PipelineId(0, 0), &builder.list);
+        debug_assert_balanced(PipelineId(0, 0), &[]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "with nothing open")]
+    fn pop_without_push_panics() {
+        let mut builder = simple_builder(PipelineId(0, 0));
+        builder.pop_stacking_context();
+        debug_assert_balanced(PipelineId(0, 0), &builder.list);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "pops a scroll layer at item 1 while a stacking context is open")]
+    fn mismatched_pop_panics() {
+        let mut builder = DisplayListBuilder::new(PipelineId(0, 0));
+        push_stacking_context(&mut builder, rect(0.0, 0.0, 100.0, 100.0));
+        builder.pop_scroll_layer();
+        debug_assert_balanced(PipelineId(0, 0), &builder.list);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "ends with 2 unclosed")]
+    fn unclosed_push_panics() {
+        let mut builder = DisplayListBuilder::new(PipelineId(0, 0));
+        push_stacking_context(&mut builder, rect(0.0, 0.0, 100.0, 100.0));
+        push_stacking_context(&mut builder, rect(0.0, 0.0, 10.0, 10.0));
+        debug_assert_balanced(PipelineId(0, 0), &builder.list);
+    }
+
+    #[test]
+    fn visit_stacking_contexts_is_preorder_across_iframes() {
+        let mut root = DisplayListBuilder::new(PipelineId(0, 0));
+        push_stacking_context(&mut root, rect(0.0, 0.0, 100.0, 100.0));
+        push_stacking_context(&mut root, rect(1.0, 0.0, 10.0, 10.0));
+        root.pop_stacking_context();
+        push_iframe(&mut root, rect(0.0, 0.0, 50.0, 50.0), PipelineId(0, 1));
+        root.pop_stacking_context();
+        let mut child = DisplayListBuilder::new(PipelineId(0, 1));
+        push_stacking_context(&mut child, rect(2.0, 0.0, 50.0, 50.0));
+        child.pop_stacking_context();
+        let mut scene = Scene::new();
+        set_display_list(&mut scene, root, 1, None).unwrap();
+        set_display_list(&mut scene, child, 1, None).unwrap();
+
+        let mut visited = Vec::new();
+        scene.visit_stacking_contexts(PipelineId(0, 0), &mut |pipeline_id, stacking_context, depth| {
+            visited.push((pipeline_id, stacking_context.bounds.origin.x, depth));
+        });
+        assert_eq!(visited, vec![(PipelineId(0, 0), 0.0, 0),
+                                 (PipelineId(0, 0), 1.0, 1),
+                                 (PipelineId(0, 1), 2.0, 1)]);
+
+        let mut visited = 0;
+        scene.visit_stacking_contexts(PipelineId(0, 5), &mut |_, _, _| visited += 1);
+        assert_eq!(visited, 0);
+    }
+
+    #[test]
+    fn visit_stacking_contexts_skips_iframe_cycles() {
+        let mut first = simple_builder(PipelineId(0, 0));
+        push_iframe(&mut first, rect(0.0, 0.0, 50.0, 50.0), PipelineId(0, 1));
+        let mut second = simple_builder(PipelineId(0, 1));
+        push_iframe(&mut second, rect(0.0, 0.0, 50.0, 50.0), PipelineId(0, 0));
+        let mut scene = Scene::new();
+        set_display_list(&mut scene, first, 1, None).unwrap();
+        set_display_list(&mut scene, second, 1, None).unwrap();
+
+        let mut visited = Vec::new();
+        scene.visit_stacking_contexts(PipelineId(0, 0), &mut |pipeline_id, _, depth| {
+            visited.push((pipeline_id, depth));
+        });
+        assert_eq!(visited, vec![(PipelineId(0, 0), 0), (PipelineId(0, 1), 0)]);
+    }
+
+    #[test]
+    fn rename_pipeline_updates_references() {
+        let (old_id, new_id) = (PipelineId(0, 1), PipelineId(0, 9));
+        let mut root = simple_builder(PipelineId(0, 0));
+        push_iframe(&mut root, rect(0.0, 0.0, 50.0, 50.0), old_id);
+        let mut scene = Scene::new();
+        scene.set_epoch_history_length(4);
+        set_display_list(&mut scene, root, 1, None).unwrap();
+        set_display_list(&mut scene, simple_builder(old_id), 3, None).unwrap();
+        scene.set_root_pipeline_id(old_id);
+        scene.freeze_pipeline(old_id, true);
+
+        assert_eq!(scene.rename_pipeline(old_id, new_id), Ok(()));
+        assert!(!scene.pipeline_map.contains_key(&old_id));
+        assert!(!scene.display_lists.contains_key(&old_id));
+        assert_eq!(scene.pipeline_map[&new_id].pipeline_id, new_id);
+        assert_eq!(scene.pipeline_map[&new_id].epoch, Epoch(3));
+        assert_eq!(scene.root_pipeline_id, Some(new_id));
+        assert!(scene.is_pipeline_frozen(new_id));
+        assert!(!scene.is_pipeline_frozen(old_id));
+        assert_eq!(scene.epoch_history(new_id), vec![Epoch(3)]);
+        assert!(scene.epoch_history(old_id).is_empty());
+        assert_eq!(scene.validate_display_list(), Vec::new());
+    }
+
+    #[test]
+    fn rename_pipeline_rejects_unknown_and_existing_ids() {
+        let mut scene = scene_with(&[PipelineId(0, 0), PipelineId(0, 1)]);
+        let snapshot = scene.snapshot();
+        assert_eq!(scene.rename_pipeline(PipelineId(0, 5), PipelineId(0, 6)),
+                   Err(SceneError::UnknownPipeline(PipelineId(0, 5))));
+        assert_eq!(scene.rename_pipeline(PipelineId(0, 0), PipelineId(0, 1)),
+                   Err(SceneError::DuplicatePipeline(PipelineId(0, 1))));
+        assert!(scene.matches_snapshot(&snapshot));
+
+        // renaming to the same id is a no-op
+        assert_eq!(scene.rename_pipeline(PipelineId(0, 0), PipelineId(0, 0)), Ok(()));
+        assert!(scene.matches_snapshot(&snapshot));
+    }
+
+    #[test]
+    fn epoch_history_is_off_by_default() {
+        let pipeline_id = PipelineId(0, 0);
+        let mut scene = scene_with(&[pipeline_id]);
+        scene.touch_pipeline(pipeline_id, Epoch(2)).unwrap();
+        assert!(scene.epoch_history(pipeline_id).is_empty());
+    }
+
+    #[test]
+    fn epoch_history_keeps_the_most_recent_epochs() {
+        let pipeline_id = PipelineId(0, 0);
+        let mut scene = Scene::new();
+        scene.set_epoch_history_length(3);
+        for epoch in 1..4 {
+            set_display_list(&mut scene, simple_builder(pipeline_id), epoch, None).unwrap();
+        }
+        scene.touch_pipeline(pipeline_id, Epoch(5)).unwrap();
+        assert_eq!(scene.epoch_history(pipeline_id), vec![Epoch(2), Epoch(3), Epoch(5)]);
+
+        // stale touches are rejected and not recorded
+        assert_eq!(scene.touch_pipeline(pipeline_id, Epoch(5)),
+                   Err(SceneError::StaleEpoch(pipeline_id, Epoch(5))));
+        assert_eq!(scene.touch_pipeline(PipelineId(0, 3), Epoch(1)),
+                   Err(SceneError::UnknownPipeline(PipelineId(0, 3))));
+        assert_eq!(scene.epoch_history(pipeline_id), vec![Epoch(2), Epoch(3), Epoch(5)]);
+
+        scene.set_epoch_history_length(1);
+        assert_eq!(scene.epoch_history(pipeline_id), vec![Epoch(5)]);
+
+        scene.set_epoch_history_length(0);
+        assert!(scene.epoch_history(pipeline_id).is_empty());
+        scene.touch_pipeline(pipeline_id, Epoch(6)).unwrap();
+        assert!(scene.epoch_history(pipeline_id).is_empty());
+    }
+
+    #[test]
+    fn report_memory_covers_a_populated_scene() {
+        let mut scene = scene_with(&[PipelineId(0, 0), PipelineId(0, 1)]);
+        scene.set_epoch_history_length(2);
+        scene.touch_pipeline(PipelineId(0, 0), Epoch(2)).unwrap();
+
+        let mut report = Vec::new();
+        scene.report_memory(&mut |name, size| report.push((name.to_owned(), size)));
+        let names: Vec<&str> = report.iter().map(|&(ref name, _)| &name[..]).collect();
+        assert_eq!(names, vec!["pipeline_map", "pipeline_sizes", "pipeline_auxiliary_lists",
+                               "display_lists", "frozen_pipelines", "epoch_history"]);
+        for &(ref name, size) in &report {
+            if name == "pipeline_map" || name == "display_lists" || name == "epoch_history" {
+                assert!(size > 0, "{} reported no memory", name);
+            }
+        }
+    }
+
+    #[test]
+    fn pipeline_overflow_is_the_root_stacking_context_clip() {
+        let mut builder = DisplayListBuilder::new(PipelineId(0, 0));
+        let bounds = rect(0.0, 0.0, 100.0, 100.0);
+        let clip = builder.new_clip_region(&rect(-10.0, -20.0, 300.0, 200.0), Vec::new(), None);
+        builder.push_stacking_context(ScrollPolicy::Scrollable,
+                                      bounds,
+                                      clip,
+                                      0,
+                                      &LayoutTransform::identity(),
+                                      &LayoutTransform::identity(),
+                                      MixBlendMode::Normal,
+                                      Vec::new());
+        push_stacking_context(&mut builder, rect(0.0, 0.0, 10.0, 10.0));
+        builder.pop_stacking_context();
+        builder.pop_stacking_context();
+        let mut scene = Scene::new();
+        set_display_list(&mut scene, builder, 1, None).unwrap();
+        set_display_list(&mut scene, DisplayListBuilder::new(PipelineId(0, 1)), 1, None).unwrap();
+
+        assert_eq!(scene.pipeline_overflow(PipelineId(0, 0)), Some(rect(-10.0, -20.0, 300.0, 200.0)));
+        // a pipeline without a root stacking context, or without a display
+        // list, has no overflow
+        assert_eq!(scene.pipeline_overflow(PipelineId(0, 1)), None);
+        assert_eq!(scene.pipeline_overflow(PipelineId(0, 2)), None);
+    }
+
+    #[test]
+    fn committed_epochs_are_the_latest_per_pipeline() {
+        let mut scene = Scene::new();
+        assert!(scene.committed_epochs().is_empty());
+        set_display_list(&mut scene, simple_builder(PipelineId(0, 0)), 1, None).unwrap();
+        set_display_list(&mut scene, simple_builder(PipelineId(0, 1)), 4, None).unwrap();
+        set_display_list(&mut scene, simple_builder(PipelineId(0, 0)), 3, None).unwrap();
+
+        let epochs = scene.committed_epochs();
+        assert_eq!(epochs.len(), 2);
+        assert_eq!(epochs[&PipelineId(0, 0)], Epoch(3));
+        assert_eq!(epochs[&PipelineId(0, 1)], Epoch(4));
+    }
+
+    #[test]
+    fn cull_rect_survives_display_list_updates() {
+        let pipeline_id = PipelineId(0, 0);
+        let mut scene = scene_with(&[pipeline_id]);
+        assert_eq!(scene.cull_rect(pipeline_id), None);
+
+        let cull_rect = rect(10.0, 20.0, 30.0, 40.0);
+        scene.set_cull_rect(pipeline_id, Some(cull_rect));
+        assert_eq!(scene.cull_rect(pipeline_id), Some(cull_rect));
+
+        set_display_list(&mut scene, simple_builder(pipeline_id), 2, None).unwrap();
+        assert_eq!(scene.cull_rect(pipeline_id), Some(cull_rect));
+
+        scene.set_cull_rect(pipeline_id, None);
+        assert_eq!(scene.cull_rect(pipeline_id), None);
+
+        // pipelines without a display list are ignored
+        scene.set_cull_rect(PipelineId(0, 1), Some(cull_rect));
+        assert_eq!(scene.cull_rect(PipelineId(0, 1)), None);
+    }
+
+    #[test]
+    fn to_dot_follows_iframes_and_marks_back_edges() {
+        let mut root = simple_builder(PipelineId(0, 0));
+        push_iframe(&mut root, rect(0.0, 0.0, 50.0, 50.0), PipelineId(0, 1));
+        let mut child = simple_builder(PipelineId(0, 1));
+        push_iframe(&mut child, rect(0.0, 0.0, 20.0, 20.0), PipelineId(0, 0));
+        let mut scene = Scene::new();
+        set_display_list(&mut scene, root, 1, None).unwrap();
+        set_display_list(&mut scene, child, 1, None).unwrap();
+
+        let dot = scene.to_dot(PipelineId(0, 0));
+        assert!(dot.starts_with("digraph scene {\n"));
+        assert!(dot.ends_with("}\n"));
+        // the root pipeline is n0, its iframe n3 and the child pipeline n4,
+        // whose own iframe n7 leads back to the root
+        assert!(dot.contains("  n0 [label=\"pipeline PipelineId(0, 0)\" shape=box];\n"));
+        assert!(dot.contains("  n0 -> n3;\n"));
+        assert!(dot.contains("  n4 [label=\"pipeline PipelineId(0, 1)\" shape=box];\n"));
+        assert!(dot.contains("  n3 -> n4;\n"));
+        assert!(dot.contains("  n7 -> n0 [style=dashed label=\"back edge\"];\n"));
+
+        assert!(scene.to_dot(PipelineId(0, 5)).contains("pipeline PipelineId(0, 5) (missing)"));
+    }
+
+    #[test]
+    fn touch_pipeline_advances_the_epoch_only() {
+        let pipeline_id = PipelineId(0, 0);
+        let mut scene = scene_with(&[pipeline_id, PipelineId(0, 1)]);
+        let items = scene.display_lists[&pipeline_id].clone();
+
+        assert_eq!(scene.touch_pipeline(pipeline_id, Epoch(2)), Ok(()));
+        assert_eq!(scene.pipeline_map[&pipeline_id].epoch, Epoch(2));
+        assert_eq!(scene.display_lists[&pipeline_id], items);
+        // other pipelines are left alone
+        assert_eq!(scene.pipeline_map[&PipelineId(0, 1)].epoch, Epoch(1));
+
+        assert_eq!(scene.touch_pipeline(pipeline_id, Epoch(1)),
+                   Err(SceneError::StaleEpoch(pipeline_id, Epoch(2))));
+        assert_eq!(scene.pipeline_map[&pipeline_id].epoch, Epoch(2));
+    }
+
+    #[test]
+    fn items_at_lists_overlapping_drawing_items_in_paint_order() {
+        let mut builder = DisplayListBuilder::new(PipelineId(0, 0));
+        push_stacking_context(&mut builder, rect(0.0, 0.0, 100.0, 100.0));
+        push_rect(&mut builder, rect(10.0, 10.0, 50.0, 50.0));
+        push_rect(&mut builder, rect(30.0, 30.0, 50.0, 50.0));
+        push_iframe(&mut builder, rect(0.0, 0.0, 100.0, 100.0), PipelineId(0, 1));
+        push_rect(&mut builder, rect(70.0, 70.0, 10.0, 10.0));
+        builder.pop_stacking_context();
+        let mut scene = Scene::new();
+        set_display_list(&mut scene, builder, 1, None).unwrap();
+
+        assert_eq!(scene.items_at(PipelineId(0, 0), &LayerPoint::new(40.0, 40.0)), vec![1, 2]);
+        assert_eq!(scene.items_at(PipelineId(0, 0), &LayerPoint::new(75.0, 75.0)), vec![2, 4]);
+        // only the stacking context and the iframe cover this point
+        assert!(scene.items_at(PipelineId(0, 0), &LayerPoint::new(5.0, 5.0)).is_empty());
+        assert!(scene.items_at(PipelineId(0, 1), &LayerPoint::new(40.0, 40.0)).is_empty());
+    }
+
+    #[test]
+    fn total_item_count_sums_every_display_list() {
+        let mut scene = Scene::new();
+        assert_eq!(scene.total_item_count(), 0);
+
+        // simple_builder makes three items: a push, a rect and a pop
+        set_display_list(&mut scene, simple_builder(PipelineId(0, 0)), 1, None).unwrap();
+        let mut builder = simple_builder(PipelineId(0, 1));
+        push_rect(&mut builder, rect(0.0, 0.0, 5.0, 5.0));
+        push_iframe(&mut builder, rect(0.0, 0.0, 5.0, 5.0), PipelineId(0, 0));
+        set_display_list(&mut scene, builder, 1, None).unwrap();
+        set_display_list(&mut scene, DisplayListBuilder::new(PipelineId(0, 2)), 1, None).unwrap();
+        assert_eq!(scene.total_item_count(), 8);
+
+        // replacing a display list replaces its items
+        set_display_list(&mut scene, DisplayListBuilder::new(PipelineId(0, 1)), 2, None).unwrap();
+        assert_eq!(scene.total_item_count(), 3);
+    }
+
+    #[test]
+    fn iter_display_items_visits_every_item_with_its_depth() {
+        let mut builder = DisplayListBuilder::new(PipelineId(0, 0));
+        push_stacking_context(&mut builder, rect(0.0, 0.0, 100.0, 100.0));
+        push_rect(&mut builder, rect(0.0, 0.0, 10.0, 10.0));
+        push_stacking_context(&mut builder, rect(0.0, 0.0, 50.0, 50.0));
+        push_rect(&mut builder, rect(0.0, 0.0, 10.0, 10.0));
+        builder.pop_stacking_context();
+        push_iframe(&mut builder, rect(0.0, 0.0, 50.0, 50.0), PipelineId(0, 1));
+        builder.pop_stacking_context();
+        let mut scene = Scene::new();
+        set_display_list(&mut scene, builder, 1, None).unwrap();
+        set_display_list(&mut scene, simple_builder(PipelineId(0, 1)), 1, None).unwrap();
+
+        let (mut pushes, mut pops, mut rects, mut iframes) = (0, 0, 0, 0);
+        let mut root_items = Vec::new();
+        scene.iter_display_items(|pipeline_id, index, depth, item| {
+            match item.item {
+                SpecificDisplayItem::PushStackingContext(..) => pushes += 1,
+                SpecificDisplayItem::PopStackingContext => pops += 1,
+                SpecificDisplayItem::Rectangle(..) => rects += 1,
+                SpecificDisplayItem::Iframe(..) => iframes += 1,
+                _ => panic!("unexpected item {:?}", item),
+            }
+            if pipeline_id == PipelineId(0, 0) {
+                root_items.push((index, depth));
+            }
+        });
+        assert_eq!((pushes, pops, rects, iframes), (3, 3, 3, 1));
+        // a push is at the depth it opens from, and a pop at the depth it
+        // returns to
+        assert_eq!(root_items, vec![(0, 0), (1, 1), (2, 1), (3, 2), (4, 1), (5, 1), (6, 0)]);
+    }
+
+    #[test]
+    fn shrink_to_fit_leaves_the_contents_unchanged() {
+        let mut scene = scene_with(&[PipelineId(0, 0), PipelineId(0, 1), PipelineId(0, 2)]);
+        scene.set_root_pipeline_id(PipelineId(0, 0));
+        scene.display_lists.reserve(64);
+        let items = scene.display_lists[&PipelineId(0, 1)].clone();
+        let snapshot = scene.snapshot();
+
+        scene.shrink_to_fit();
+        assert!(scene.matches_snapshot(&snapshot));
+        assert_eq!(scene.display_lists[&PipelineId(0, 1)], items);
+        assert_eq!(scene.validate_display_list(), Vec::new());
     }
 }
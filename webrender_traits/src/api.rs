@@ -6,11 +6,12 @@ use byteorder::{LittleEndian, WriteBytesExt};
 use channel::{self, MsgSender, PayloadHelperMethods, PayloadSender};
 use offscreen_gl_context::{GLContextAttributes, GLLimits};
 use std::cell::Cell;
-use {ApiMsg, ColorF, DisplayListBuilder, Epoch};
+use std::collections::HashMap;
+use {ApiMsg, ColorF, DisplayItem, DisplayListBuilder, Epoch};
 use {FontKey, IdNamespace, ImageFormat, ImageKey, NativeFontHandle, PipelineId};
 use {RenderApiSender, ResourceId, ScrollEventPhase, ScrollLayerState, ScrollLocation, ServoScrollRootId};
 use {GlyphKey, GlyphDimensions, ImageData, WebGLContextId, WebGLCommand};
-use {DeviceIntSize, LayoutPoint, LayoutSize, WorldPoint};
+use {DeviceIntSize, LayoutPoint, LayoutRect, LayoutSize, WorldPoint};
 use VRCompositorCommand;
 
 impl RenderApiSender {
@@ -136,6 +137,31 @@ impl RenderApi {
         self.api_sender.send(msg).unwrap();
     }
 
+    /// Sets the rect used to cull a pipeline's content, e.g. its visible part
+    /// of the viewport, or clears it with None. The rect is kept when the
+    /// pipeline's display list is replaced. Pipelines that haven't been
+    /// given a display list yet are ignored.
+    pub fn set_cull_rect(&self, pipeline_id: PipelineId, cull_rect: Option<LayoutRect>) {
+        let msg = ApiMsg::SetCullRect(pipeline_id, cull_rect);
+        self.api_sender.send(msg).unwrap();
+    }
+
+    /// Advances a pipeline's epoch to `epoch` without changing its display
+    /// list, forcing a recomposite. Ignored unless the pipeline has a display
+    /// list and `epoch` is greater than its current epoch.
+    pub fn touch_pipeline(&self, pipeline_id: PipelineId, epoch: Epoch) {
+        let msg = ApiMsg::TouchPipeline(pipeline_id, epoch);
+        self.api_sender.send(msg).unwrap();
+    }
+
+    /// Freezes a pipeline, or unfreezes it if `frozen` is false. Display
+    /// lists sent for a frozen pipeline are dropped, so it keeps showing its
+    /// current content while other pipelines update.
+    pub fn freeze_pipeline(&self, pipeline_id: PipelineId, frozen: bool) {
+        let msg = ApiMsg::FreezePipeline(pipeline_id, frozen);
+        self.api_sender.send(msg).unwrap();
+    }
+
     /// Supplies a new frame to WebRender.
     ///
     /// Non-blocking, it notifies a worker process which processes the display list.
@@ -214,6 +240,74 @@ impl RenderApi {
         rx.recv().unwrap()
     }
 
+    /// Returns the approximate heap memory used by the scene, in bytes, with
+    /// the name of the map each amount is for, for memory reporters.
+    pub fn get_scene_memory_report(&self) -> Vec<(String, usize)> {
+        let (tx, rx) = channel::msg_channel().unwrap();
+        let msg = ApiMsg::GetSceneMemoryReport(tx);
+        self.api_sender.send(msg).unwrap();
+        rx.recv().unwrap()
+    }
+
+    /// Returns the overflow rect of a pipeline's root stacking context, e.g.
+    /// to size scrollbars or compositor surfaces, or None if the pipeline
+    /// has no display list.
+    pub fn get_pipeline_overflow(&self, pipeline_id: PipelineId) -> Option<LayoutRect> {
+        let (tx, rx) = channel::msg_channel().unwrap();
+        let msg = ApiMsg::GetPipelineOverflow(pipeline_id, tx);
+        self.api_sender.send(msg).unwrap();
+        rx.recv().unwrap()
+    }
+
+    /// Returns the epoch most recently committed for each pipeline, so the
+    /// embedder can tell its content which frames have been composited.
+    pub fn get_committed_epochs(&self) -> HashMap<PipelineId, Epoch> {
+        let (tx, rx) = channel::msg_channel().unwrap();
+        let msg = ApiMsg::GetCommittedEpochs(tx);
+        self.api_sender.send(msg).unwrap();
+        rx.recv().unwrap()
+    }
+
+    /// Returns the stacking context tree reachable from a pipeline, including
+    /// the pipelines its iframes show, in Graphviz dot format, for debugging.
+    pub fn get_scene_dot(&self, pipeline_id: PipelineId) -> String {
+        let (tx, rx) = channel::msg_channel().unwrap();
+        let msg = ApiMsg::GetSceneDot(pipeline_id, tx);
+        self.api_sender.send(msg).unwrap();
+        rx.recv().unwrap()
+    }
+
+    /// Returns the indices, in paint order, of a pipeline's drawing items
+    /// whose rects contain `point`, for debugging overlapping items. The
+    /// rects are compared as given in the display list, ignoring clips and
+    /// transforms, so this isn't a hit test.
+    pub fn get_items_at(&self, pipeline_id: PipelineId, point: &LayoutPoint) -> Vec<usize> {
+        let (tx, rx) = channel::msg_channel().unwrap();
+        let msg = ApiMsg::GetItemsAt(pipeline_id, *point, tx);
+        self.api_sender.send(msg).unwrap();
+        rx.recv().unwrap()
+    }
+
+    /// Returns the number of display items across all pipelines. Cheap
+    /// enough to poll every frame to spot runaway display lists.
+    pub fn get_display_item_count(&self) -> usize {
+        let (tx, rx) = channel::msg_channel().unwrap();
+        let msg = ApiMsg::GetDisplayItemCount(tx);
+        self.api_sender.send(msg).unwrap();
+        rx.recv().unwrap()
+    }
+
+    /// Returns every display item in the scene with the pipeline it's in,
+    /// its index in that pipeline's display list and the stacking context
+    /// nesting depth it sits at, for tools that inspect or export the scene.
+    /// Pipelines are in no particular order.
+    pub fn get_display_items(&self) -> Vec<(PipelineId, usize, usize, DisplayItem)> {
+        let (tx, rx) = channel::msg_channel().unwrap();
+        let msg = ApiMsg::GetDisplayItems(tx);
+        self.api_sender.send(msg).unwrap();
+        rx.recv().unwrap()
+    }
+
     pub fn request_webgl_context(&self, size: &DeviceIntSize, attributes: GLContextAttributes)
                                  -> Result<(WebGLContextId, GLLimits), String> {
         let (tx, rx) = channel::msg_channel().unwrap();
@@ -10,6 +10,7 @@ use channel::{PayloadSender, MsgSender};
 #[cfg(feature = "nightly")]
 use core::nonzero::NonZero;
 use offscreen_gl_context::{GLContextAttributes, GLLimits};
+use std::collections::HashMap;
 use std::sync::Arc;
 
 #[cfg(target_os = "macos")] use core_graphics::font::CGFont;
@@ -45,11 +46,35 @@ pub enum ApiMsg {
                        BuiltDisplayListDescriptor,
                        AuxiliaryListsDescriptor),
     SetRootPipeline(PipelineId),
+    /// Sets the culling rect of a pipeline, or clears it with None.
+    SetCullRect(PipelineId, Option<LayoutRect>),
+    /// Advances a pipeline's epoch without changing its display list.
+    TouchPipeline(PipelineId, Epoch),
+    /// Freezes or unfreezes a pipeline against display list updates.
+    FreezePipeline(PipelineId, bool),
     Scroll(ScrollLocation, WorldPoint, ScrollEventPhase),
     ScrollLayersWithScrollId(LayoutPoint, PipelineId, ServoScrollRootId),
     TickScrollingBounce,
     TranslatePointToLayerSpace(WorldPoint, MsgSender<(LayoutPoint, PipelineId)>),
     GetScrollLayerState(MsgSender<Vec<ScrollLayerState>>),
+    /// Gets the approximate heap memory used by the scene, in bytes, for each
+    /// of its maps.
+    GetSceneMemoryReport(MsgSender<Vec<(String, usize)>>),
+    /// Gets the overflow rect of a pipeline's root stacking context.
+    GetPipelineOverflow(PipelineId, MsgSender<Option<LayoutRect>>),
+    /// Gets the epoch most recently committed for each pipeline.
+    GetCommittedEpochs(MsgSender<HashMap<PipelineId, Epoch>>),
+    /// Gets the stacking context tree reachable from a pipeline in Graphviz
+    /// dot format.
+    GetSceneDot(PipelineId, MsgSender<String>),
+    /// Gets the indices of a pipeline's drawing items whose rects contain a
+    /// point.
+    GetItemsAt(PipelineId, LayoutPoint, MsgSender<Vec<usize>>),
+    /// Gets the number of display items across all pipelines.
+    GetDisplayItemCount(MsgSender<usize>),
+    /// Gets every display item in the scene with its pipeline, its index in
+    /// that pipeline's display list and its stacking context depth.
+    GetDisplayItems(MsgSender<Vec<(PipelineId, usize, usize, DisplayItem)>>),
     RequestWebGLContext(DeviceIntSize, GLContextAttributes, MsgSender<Result<(WebGLContextId, GLLimits), String>>),
     ResizeWebGLContext(WebGLContextId, DeviceIntSize),
     WebGLCommand(WebGLContextId, WebGLCommand),
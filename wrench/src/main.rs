@@ -28,7 +28,9 @@ extern crate font_loader;
 
 use gleam::gl;
 use glutin::{ElementState, VirtualKeyCode};
+use std::fs;
 use std::path::PathBuf;
+use std::process;
 use std::cmp::{min, max};
 use std::ffi::CStr;
 use webrender_traits::*;
@@ -43,6 +45,7 @@ use yaml_frame_reader::YamlFrameReader;
 
 mod yaml_frame_writer;
 mod json_frame_writer;
+mod yaml_validator;
 
 mod binary_frame_reader;
 use binary_frame_reader::BinaryFrameReader;
@@ -57,6 +60,7 @@ lazy_static! {
 
     static ref WHITE_COLOR: ColorF = ColorF::new(1.0, 1.0, 1.0, 1.0);
     static ref BLACK_COLOR: ColorF = ColorF::new(0.0, 0.0, 0.0, 1.0);
+    static ref DEFAULT_BACKGROUND_COLOR: ColorF = ColorF::new(0.3, 0.0, 0.0, 1.0);
 }
 
 pub static mut CURRENT_FRAME_NUMBER: u32 = 0;
@@ -102,12 +106,34 @@ fn make_window(size: DeviceUintSize,
     window
 }
 
+/// Makes a GL context without a window, for runs that never render.
+fn make_headless_context(size: DeviceUintSize) -> glutin::HeadlessContext {
+    let context = glutin::HeadlessRendererBuilder::new(size.width, size.height)
+        .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (3, 2)))
+        .build()
+        .unwrap();
+
+    unsafe {
+        context.make_current().ok();
+        gl::load_with(|symbol| context.get_proc_address(symbol) as *const _);
+    }
+
+    context
+}
+
 fn main() {
     let args_yaml = load_yaml!("args.yaml");
     let args = clap::App::from_yaml(args_yaml)
         .setting(clap::AppSettings::ArgRequiredElseHelp)
         .get_matches();
 
+    // --validate-dir runs each file in a wrench of its own, so this one
+    // never opens a window
+    if let Some(dir) = args.value_of("validate-dir") {
+        let passed = yaml_validator::validate_dir(&PathBuf::from(dir));
+        process::exit(if passed { 0 } else { 1 });
+    }
+
     // handle some global arguments
     let res_path = args.value_of("shaders").map(|s| PathBuf::from(s));
     let dp_ratio = args.value_of("dp_ratio").map(|v| v.parse::<f32>().unwrap());
@@ -117,6 +143,13 @@ fn main() {
         else if s == "json" { wrench::SaveType::Json }
         else { panic!("Save type must be json or yaml"); }
     });
+    // --roundtrip writes the scene a YAML file builds back out as YAML
+    let roundtrip_file = args.subcommand_matches("show").and_then(|subargs| subargs.value_of("roundtrip"));
+    let save_type = match (save_type, roundtrip_file) {
+        (Some(_), Some(_)) => panic!("--roundtrip can't be used with --save"),
+        (save_type, None) => save_type,
+        (None, Some(file)) => Some(wrench::SaveType::YamlFile(PathBuf::from(file))),
+    };
     let size = args.value_of("size").map(|s| {
         let x = s.find('x').expect("Size must be specified exactly as widthxheight");
         let w = s[0..x].parse::<u32>().expect("Invalid size width");
@@ -124,9 +157,28 @@ fn main() {
         DeviceUintSize::new(w, h)
     }).unwrap_or(DeviceUintSize::new(1920, 1080));
 
-    let mut window = make_window(size, dp_ratio, args.is_present("vsync"));
+    // --validate builds the display lists for each document and stops
+    // there; the reader panics on anything it can't build. Nothing is
+    // rendered, so no window is opened.
+    if let Some(subargs) = args.subcommand_matches("show") {
+        if subargs.is_present("validate") {
+            let _context = make_headless_context(size);
+            let mut wrench = Wrench::new(None,
+                                         res_path,
+                                         dp_ratio.unwrap_or(1.0),
+                                         None,
+                                         size,
+                                         args.is_present("rebuild"),
+                                         args.is_present("subpixel-aa"),
+                                         args.is_present("debug"));
+            YamlFrameReader::new_from_args(subargs).validate(&mut wrench);
+            return;
+        }
+    }
+
+    let window = make_window(size, dp_ratio, args.is_present("vsync"));
     let dp_ratio = dp_ratio.unwrap_or(window.hidpi_factor());
-    let mut wrench = Wrench::new(&mut window,
+    let mut wrench = Wrench::new(Some(window.create_window_proxy()),
                                  res_path,
                                  dp_ratio,
                                  save_type,
@@ -146,7 +198,33 @@ fn main() {
 
     let mut show_help = false;
     let mut profiler = false;
-    let mut do_loop = false;
+    // --loop starts playing through a sequence straight away
+    let mut do_loop = args.subcommand_matches("show").map_or(false, |subargs| subargs.is_present("loop"));
+
+    // --frames-out writes each frame of the document to a numbered PNG,
+    // stepping through a sequence and then exiting after the last one.
+    let frames_out = args.subcommand_matches("show")
+        .and_then(|subargs| subargs.value_of("frames-out"))
+        .map(|dir| PathBuf::from(dir));
+    if let Some(ref dir) = frames_out {
+        fs::create_dir_all(dir).expect("couldn't create the --frames-out directory");
+        do_loop = false;
+    }
+    // --capture steps through a sequence to its capture_frame, writes that
+    // frame to a PNG and exits.
+    let capture_file = args.subcommand_matches("show")
+        .and_then(|subargs| subargs.value_of("capture"))
+        .map(|file| PathBuf::from(file));
+    if capture_file.is_some() {
+        if frames_out.is_some() {
+            panic!("--capture can't be used with --frames-out");
+        }
+        do_loop = false;
+    }
+    let mut frames_written = 0;
+    // The number of frames to render before the current document's reaches
+    // the screen. Each new document takes as many frames as are queued.
+    let mut frames_until_capture = 0;
 
     let queue_frames = thing.thing().queue_frames();
     for _ in 0..queue_frames {
@@ -200,6 +278,32 @@ fn main() {
 
                 wrench.render();
 
+                if let Some(ref dir) = frames_out {
+                    if frames_until_capture > 0 {
+                        frames_until_capture -= 1;
+                    } else {
+                        frames_written += 1;
+                        wrench.save_frame(&dir.join(format!("frame_{:04}.png", frames_written)));
+                        if thing.is_last_frame() {
+                            break;
+                        }
+                        thing.next_frame();
+                        frames_until_capture = queue_frames;
+                    }
+                }
+
+                if let Some(ref file) = capture_file {
+                    if frames_until_capture > 0 {
+                        frames_until_capture -= 1;
+                    } else if thing.is_capture_frame() {
+                        wrench.save_frame(file);
+                        break;
+                    } else {
+                        thing.next_frame();
+                        frames_until_capture = queue_frames;
+                    }
+                }
+
                 //gl::flush();
                 window.swap_buffers().ok();
 
@@ -9,7 +9,6 @@ use dwrote;
 use font_loader::system_fonts;
 
 use gleam::gl;
-use glutin;
 use glutin::{WindowProxy, ElementState, VirtualKeyCode};
 use image;
 use image::GenericImage;
@@ -30,16 +29,19 @@ use {CURRENT_FRAME_NUMBER, WHITE_COLOR, BLACK_COLOR};
 pub enum SaveType {
     Yaml,
     Json,
+    /// Writes the root display list of each frame to one YAML file.
+    YamlFile(PathBuf),
 }
 
 struct Notifier {
-    window_proxy: WindowProxy,
+    /// The window to wake when a frame is ready, if there is one.
+    window_proxy: Option<WindowProxy>,
     frames_notified: u32,
     timing_receiver: chase_lev::Stealer<time::SteadyTime>,
 }
 
 impl Notifier {
-    fn new(window_proxy: WindowProxy, timing_receiver: chase_lev::Stealer<time::SteadyTime>) -> Notifier {
+    fn new(window_proxy: Option<WindowProxy>, timing_receiver: chase_lev::Stealer<time::SteadyTime>) -> Notifier {
         Notifier {
             window_proxy: window_proxy,
             frames_notified: 0,
@@ -64,11 +66,15 @@ impl RenderNotifier for Notifier {
                 println!("Notified of frame, but no frame was ready?");
             }
         }
-        self.window_proxy.wakeup_event_loop();
+        if let Some(ref window_proxy) = self.window_proxy {
+            window_proxy.wakeup_event_loop();
+        }
     }
 
     fn new_scroll_frame_ready(&mut self, _composite_needed: bool) {
-        self.window_proxy.wakeup_event_loop();
+        if let Some(ref window_proxy) = self.window_proxy {
+            window_proxy.wakeup_event_loop();
+        }
     }
 
     fn pipeline_size_changed(&mut self,
@@ -102,11 +108,97 @@ pub fn layout_simple_ascii(face: NativeFontHandle, text: &str, size: Au) -> (Vec
     panic!("Can't layout simple ascii on this platform");
 }
 
+/// Returns the distance from the top of a line of text to its baseline.
+#[cfg(target_os = "windows")]
+pub fn font_ascent(face: NativeFontHandle, size: Au) -> f32 {
+    let system_fc = dwrote::FontCollection::system();
+    let font = system_fc.get_font_from_descriptor(&(face as dwrote::FontDescriptor)).unwrap();
+    let metrics = font.create_font_face().metrics();
+    metrics.ascent as f32 * size.to_f32_px() / metrics.designUnitsPerEm as f32
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn font_ascent(face: NativeFontHandle, size: Au) -> f32 {
+    panic!("Can't read font metrics on this platform");
+}
+
+/// Controls how an image file is processed before it's registered.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ImageOptions {
+    /// WebRender expects premultiplied alpha. If set, RGBA8 pixels are
+    /// premultiplied before upload; otherwise they're passed through as-is.
+    pub premultiply: bool,
+    /// The x, y, width and height of the part of the image to register,
+    /// or None for the whole image.
+    pub src_rect: Option<(u32, u32, u32, u32)>,
+    /// Pixels exactly matching this RGB color are made fully transparent.
+    /// The image is converted to RGBA8 if needed.
+    pub color_key: Option<(u8, u8, u8)>,
+    /// WebRender skips blending for images whose pixels are all opaque. If
+    /// set, an RGBA8 image's alpha channel is made fully opaque after the
+    /// other options are applied, so it takes that path whatever its pixels
+    /// were, as an opaque hint would. The color channels are unchanged.
+    pub opaque: bool,
+}
+
+fn apply_color_key(pixels: &mut [u8], color_key: (u8, u8, u8)) {
+    for pixel in pixels.chunks_mut(4) {
+        if (pixel[0], pixel[1], pixel[2]) == color_key {
+            for channel in pixel.iter_mut() {
+                *channel = 0;
+            }
+        }
+    }
+}
+
+fn premultiply_rgba8(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_mut(4) {
+        let a = pixel[3] as u32;
+        pixel[0] = ((pixel[0] as u32 * a + 127) / 255) as u8;
+        pixel[1] = ((pixel[1] as u32 * a + 127) / 255) as u8;
+        pixel[2] = ((pixel[2] as u32 * a + 127) / 255) as u8;
+    }
+}
+
+/// Applies the pixel options, in order: the color key, then premultiplying,
+/// then forcing opacity. The color key needs RGBA8 pixels.
+fn apply_pixel_options(pixels: &mut [u8], format: ImageFormat, options: &ImageOptions) {
+    if let Some(color_key) = options.color_key {
+        apply_color_key(pixels, color_key);
+    }
+    if options.premultiply && format == ImageFormat::RGBA8 {
+        premultiply_rgba8(pixels);
+    }
+    if options.opaque && format == ImageFormat::RGBA8 {
+        for pixel in pixels.chunks_mut(4) {
+            pixel[3] = 255;
+        }
+    }
+}
+
+/// Decodes an image file and crops it to the options' src_rect, if any.
+fn load_image(file: &Path, options: &ImageOptions) -> image::DynamicImage {
+    let mut image = image::open(file).unwrap();
+    if let Some((x, y, w, h)) = options.src_rect {
+        let (image_w, image_h) = image.dimensions();
+        if x + w > image_w || y + h > image_h {
+            panic!("src_rect {:?} is outside the {}x{} image {:?}",
+                   (x, y, w, h), image_w, image_h, file);
+        }
+        image = image.crop(x, y, w, h);
+    }
+    image
+}
+
 pub trait WrenchThing {
     fn next_frame(&mut self);
     fn prev_frame(&mut self);
     fn do_frame(&mut self, &mut Wrench) -> u32;
     fn queue_frames(&self) -> u32 { 0 }
+    /// Whether this is the last of the frames `next_frame` steps through.
+    fn is_last_frame(&self) -> bool { true }
+    /// Whether this is the frame `--capture` saves.
+    fn is_capture_frame(&self) -> bool { true }
 }
 
 pub struct Wrench {
@@ -118,7 +210,7 @@ pub struct Wrench {
     pub root_pipeline_id: PipelineId,
 
     sender: RenderApiSender,
-    image_map: HashMap<PathBuf, (ImageKey, LayoutSize)>,
+    image_map: HashMap<(PathBuf, ImageOptions), (ImageKey, LayoutSize)>,
 
     // internal housekeeping
     next_scroll_layer_id: usize,
@@ -132,7 +224,7 @@ pub struct Wrench {
 }
 
 impl Wrench {
-    pub fn new(window: &mut glutin::Window,
+    pub fn new(window_proxy: Option<WindowProxy>,
                shader_override_path: Option<PathBuf>,
                dp_ratio: f32,
                save_type: Option<SaveType>,
@@ -150,6 +242,8 @@ impl Wrench {
                     as Box<webrender::ApiRecordingReceiver>,
                 &SaveType::Json => Box::new(JsonFrameWriter::new(&PathBuf::from("json_frames")))
                     as Box<webrender::ApiRecordingReceiver>,
+                &SaveType::YamlFile(ref file) => Box::new(YamlFrameWriter::new_for_file(file))
+                    as Box<webrender::ApiRecordingReceiver>,
             };
             webrender::set_recording_detour(Some(recorder));
         }
@@ -167,13 +261,14 @@ impl Wrench {
             renderer_kind: RendererKind::Native,
             clear_framebuffer: true,
             clear_color: ColorF::new(1.0, 1.0, 1.0, 1.0),
+            transparent_background_policy: webrender::TransparentBackgroundPolicy::Skip,
         };
 
         let (renderer, sender) = webrender::renderer::Renderer::new(opts);
         let api = sender.create_api();
 
         let (timing_sender, timing_receiver) = chase_lev::deque();
-        let notifier = Box::new(Notifier::new(window.create_window_proxy(), timing_receiver));
+        let notifier = Box::new(Notifier::new(window_proxy, timing_receiver));
         renderer.set_render_notifier(notifier);
 
         let mut wrench = Wrench {
@@ -240,6 +335,15 @@ impl Wrench {
         (key, Some(descriptor))
     }
 
+    #[cfg(target_os = "windows")]
+    pub fn try_font_key_from_name(&mut self, font_name: &str) -> Option<(FontKey, Option<NativeFontHandle>)> {
+        let system_fc = dwrote::FontCollection::system();
+        if system_fc.get_font_family_by_name(font_name).is_none() {
+            return None;
+        }
+        Some(self.font_key_from_name(font_name))
+    }
+
     #[cfg(target_os = "windows")]
     pub fn font_key_from_yaml_table(&mut self, item: &Yaml) -> (FontKey, Option<NativeFontHandle>) {
         assert!(!item["family"].is_badvalue());
@@ -265,6 +369,11 @@ impl Wrench {
         self.font_key_from_bytes(font)
     }
 
+    #[cfg(target_os = "linux")]
+    pub fn try_font_key_from_name(&mut self, font_name: &str) -> Option<(FontKey, Option<NativeFontHandle>)> {
+        let property = system_fonts::FontPropertyBuilder::new().family(font_name).build();
+        system_fonts::get(&property).map(|(font, _)| self.font_key_from_bytes(font))
+    }
 
     #[cfg(not(target_os = "windows"))]
     pub fn font_key_from_native_handle(&mut self, descriptor: &NativeFontHandle) -> FontKey {
@@ -276,6 +385,11 @@ impl Wrench {
         panic!("Can't font_key_from_name on this platform");
     }
 
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    pub fn try_font_key_from_name(&mut self, font_name: &str) -> Option<(FontKey, Option<NativeFontHandle>)> {
+        None
+    }
+
     #[cfg(not(any(target_os = "windows", target_os = "linux")))]
     pub fn font_key_from_yaml_table(&mut self, item: &Yaml) -> (FontKey, Option<NativeFontHandle>) {
         panic!("Can't font_key_from_yaml_table on this platform");
@@ -286,27 +400,105 @@ impl Wrench {
         (key, None)
     }
 
-    pub fn add_or_get_image(&mut self, file: &Path) -> (ImageKey, LayoutSize) {
-        let key = file.to_owned();
+    /// Selects a CPAL color palette for a font, returning the key to draw
+    /// with. Only color (COLR) glyphs are affected. WebRender has no palette
+    /// support yet, so the default palette is always used and other indices
+    /// are reported and ignored; this is the one place to wire it up later.
+    pub fn font_key_with_palette(&mut self, font_key: FontKey, palette: u16) -> FontKey {
+        if palette != 0 {
+            println!("Warning: font palette {} is not supported, using palette 0", palette);
+        }
+        font_key
+    }
+
+    /// Gives the background color subpixel AA text is blended onto,
+    /// returning the key to draw with. WebRender doesn't take a background
+    /// hint yet and assumes the text is opaque over its backdrop, so this is
+    /// the one place to pass it through once it does.
+    pub fn font_key_with_subpixel_bg_color(&mut self, font_key: FontKey, _bg_color: Option<ColorF>) -> FontKey {
+        font_key
+    }
+
+    /// Loads an image file and registers it with the API. Images loaded with
+    /// different options are registered separately.
+    pub fn add_or_get_image(&mut self, file: &Path, options: ImageOptions) -> (ImageKey, LayoutSize) {
+        let key = (file.to_owned(), options);
         if let Some(k) = self.image_map.get(&key) {
             return *k
         }
 
-        let image = image::open(file).unwrap();
+        let image = load_image(file, &options);
+        let val = self.register_image(image, &options);
+        self.image_map.insert(key, val);
+        val
+    }
+
+    /// Registers each tile of at most tile_size x tile_size pixels of an
+    /// image as its own image, in rows from the top left, and returns the
+    /// rect of each tile within the image with its key. The file is decoded
+    /// at most once, and only if some tile isn't registered yet.
+    pub fn add_or_get_image_tiles(&mut self, file: &Path, options: ImageOptions, tile_size: u32)
+                                  -> Vec<(LayoutRect, ImageKey)> {
+        let (_, image_dims) = self.add_or_get_image(file, options);
+        let (width, height) = (image_dims.width as u32, image_dims.height as u32);
+        let columns = width / tile_size + if width % tile_size == 0 { 0 } else { 1 };
+        let rows = height / tile_size + if height % tile_size == 0 { 0 } else { 1 };
+        let (src_x, src_y) = options.src_rect.map_or((0, 0), |(x, y, _, _)| (x, y));
+
+        let mut image = None;
+        let mut tiles = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                let (tile_x, tile_y) = (column * tile_size, row * tile_size);
+                let tile_width = tile_size.min(width - tile_x);
+                let tile_height = tile_size.min(height - tile_y);
+                let tile_options = ImageOptions {
+                    src_rect: Some((src_x + tile_x, src_y + tile_y, tile_width, tile_height)),
+                    ..options
+                };
+                let key = (file.to_owned(), tile_options);
+                let cached_key = self.image_map.get(&key).map(|&(tile_key, _)| tile_key);
+                let tile_key = match cached_key {
+                    Some(tile_key) => tile_key,
+                    None => {
+                        if image.is_none() {
+                            image = Some(load_image(file, &options));
+                        }
+                        let tile = image.as_mut().unwrap().crop(tile_x, tile_y, tile_width, tile_height);
+                        let val = self.register_image(tile, &tile_options);
+                        self.image_map.insert(key, val);
+                        val.0
+                    }
+                };
+                let tile_rect = LayoutRect::new(LayoutPoint::new(tile_x as f32, tile_y as f32),
+                                                LayoutSize::new(tile_width as f32, tile_height as f32));
+                tiles.push((tile_rect, tile_key));
+            }
+        }
+        tiles
+    }
+
+    /// Registers a decoded image with the API, applying the pixel options.
+    fn register_image(&mut self, mut image: image::DynamicImage, options: &ImageOptions)
+                      -> (ImageKey, LayoutSize) {
+        if options.color_key.is_some() {
+            image = image::ImageRgba8(image.to_rgba());
+        }
         let image_dims = image.dimensions();
+        let format = match image {
+            image::ImageLuma8(_) => ImageFormat::A8,
+            image::ImageRgb8(_) => ImageFormat::RGB8,
+            image::ImageRgba8(_) => ImageFormat::RGBA8,
+            _ => panic!("We don't support whatever your crazy image type is, come on"),
+        };
+        let mut pixels = image.raw_pixels();
+        apply_pixel_options(&mut pixels, format, options);
         let image_key = self.api.add_image(image_dims.0, image_dims.1,
                                            None, // stride
-                                           match image {
-                                               image::ImageLuma8(_) => ImageFormat::A8,
-                                               image::ImageRgb8(_) => ImageFormat::RGB8,
-                                               image::ImageRgba8(_) => ImageFormat::RGBA8,
-                                               _ => panic!("We don't support whatever your crazy image type is, come on"),
-                                           },
-                                           ImageData::Raw(Arc::new(image.raw_pixels())));
-
-        let val = (image_key, LayoutSize::new(image_dims.0 as f32, image_dims.1 as f32));
-        self.image_map.insert(key, val);
-        val
+                                           format,
+                                           ImageData::Raw(Arc::new(pixels)));
+
+        (image_key, LayoutSize::new(image_dims.0 as f32, image_dims.1 as f32))
     }
 
     pub fn update(&mut self, dim: DeviceUintSize) {
@@ -318,21 +510,63 @@ impl Wrench {
         gl::clear(gl::COLOR_BUFFER_BIT);
     }
 
-    pub fn send_lists(&mut self, frame_number: u32, display_list: DisplayListBuilder) {
+    pub fn send_lists(&mut self,
+                      frame_number: u32,
+                      display_list: DisplayListBuilder,
+                      background_color: Option<ColorF>) {
         self.frame_start_sender.push(time::SteadyTime::now());
 
-        let root_background_color = Some(ColorF::new(0.3, 0.0, 0.0, 1.0));
-        self.api.set_root_display_list(root_background_color,
+        self.api.set_root_display_list(background_color,
                                        Epoch(frame_number),
                                        self.window_size_f32(),
                                        display_list);
     }
 
+    /// Returns the bounding box of the pixels the glyphs cover when
+    /// rasterized, or None if none of them has any.
+    pub fn glyph_ink_bounds(&mut self, font_key: FontKey, size: Au, glyphs: &[GlyphInstance]) -> Option<LayoutRect> {
+        let glyph_keys = glyphs.iter().map(|glyph| GlyphKey::new(font_key, size, glyph.index)).collect();
+        let dimensions = self.api.get_glyph_dimensions(glyph_keys);
+        glyphs.iter().zip(dimensions).filter_map(|(glyph, dimensions)| {
+            dimensions.map(|dim| {
+                LayoutRect::new(LayoutPoint::new(glyph.x + dim.left as f32, glyph.y - dim.top as f32),
+                                LayoutSize::new(dim.width as f32, dim.height as f32))
+            })
+        }).fold(None, |bounds: Option<LayoutRect>, rect| {
+            Some(bounds.map_or(rect, |bounds| bounds.union(&rect)))
+        })
+    }
+
+    /// Blocks until the render backend has handled every message sent so
+    /// far, including building the frames for any submitted display lists.
+    /// An empty glyph dimensions query is used as the round trip.
+    pub fn flush_backend(&mut self) {
+        self.api.get_glyph_dimensions(Vec::new());
+    }
+
+    pub fn set_clear_color(&mut self, color: ColorF) {
+        self.renderer.set_clear_color(color);
+    }
+
     pub fn render(&mut self) {
         self.renderer.update();
         self.renderer.render(self.window_size);
     }
 
+    /// Writes what's currently been rendered to the back buffer to a PNG.
+    pub fn save_frame(&self, path: &Path) {
+        let (width, height) = (self.window_size.width, self.window_size.height);
+        let pixels = gl::read_pixels(0, 0, width as i32, height as i32, gl::RGBA, gl::UNSIGNED_BYTE);
+        // GL rows run from the bottom up, PNG rows from the top down
+        let row_bytes = width as usize * 4;
+        let mut flipped = Vec::with_capacity(pixels.len());
+        for row in pixels.chunks(row_bytes).rev() {
+            flipped.extend_from_slice(row);
+        }
+        image::save_buffer(path, &flipped, width, height, image::ColorType::RGBA(8))
+            .expect(&format!("couldn't write frame to {:?}", path));
+    }
+
     pub fn refresh(&mut self) {
         self.frame_start_sender.push(time::SteadyTime::now());
         self.api.generate_frame();
@@ -359,3 +593,63 @@ impl Wrench {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ImageOptions, apply_pixel_options};
+    use webrender_traits::ImageFormat;
+
+    const KEY: (u8, u8, u8) = (255, 0, 255);
+
+    /// A keyed pixel followed by a half transparent red one.
+    fn pixels() -> Vec<u8> {
+        vec![255, 0, 255, 255,
+             255, 0, 0, 128]
+    }
+
+    fn options(premultiply: bool, opaque: bool) -> ImageOptions {
+        ImageOptions {
+            color_key: Some(KEY),
+            premultiply: premultiply,
+            opaque: opaque,
+            ..ImageOptions::default()
+        }
+    }
+
+    #[test]
+    fn color_key_clears_only_matching_pixels() {
+        let mut keyed = pixels();
+        apply_pixel_options(&mut keyed, ImageFormat::RGBA8, &options(false, false));
+        assert_eq!(keyed, vec![0, 0, 0, 0,
+                               255, 0, 0, 128]);
+
+        // the key compares the color channels only, and exactly
+        let mut near = vec![254, 0, 255, 255,
+                            255, 0, 255, 10];
+        apply_pixel_options(&mut near, ImageFormat::RGBA8, &options(false, false));
+        assert_eq!(near, vec![254, 0, 255, 255,
+                              0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn color_key_is_applied_before_premultiplying() {
+        let mut keyed = pixels();
+        apply_pixel_options(&mut keyed, ImageFormat::RGBA8, &options(true, false));
+        assert_eq!(keyed, vec![0, 0, 0, 0,
+                               128, 0, 0, 128]);
+    }
+
+    #[test]
+    fn opaque_overrides_the_color_key_alpha() {
+        // opaque is applied last, so keyed pixels end up opaque black
+        let mut keyed = pixels();
+        apply_pixel_options(&mut keyed, ImageFormat::RGBA8, &options(false, true));
+        assert_eq!(keyed, vec![0, 0, 0, 255,
+                               255, 0, 0, 255]);
+
+        let mut keyed = pixels();
+        apply_pixel_options(&mut keyed, ImageFormat::RGBA8, &options(true, true));
+        assert_eq!(keyed, vec![0, 0, 0, 255,
+                               128, 0, 0, 255]);
+    }
+}
@@ -4,25 +4,426 @@
 
 use app_units::Au;
 use clap;
+use std::char;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
+use std::mem;
 use std::path::{Path, PathBuf};
+use time;
 use webrender_traits::*;
-use yaml_helper::YamlHelper;
+use yaml_helper::{YamlHelper, parse_css_transform};
 use yaml_rust::{Yaml, YamlLoader};
+use yaml_rust::yaml::Hash as Table;
 
-use wrench::{Wrench, WrenchThing, layout_simple_ascii};
-use {WHITE_COLOR, PLATFORM_DEFAULT_FACE_NAME};
+use wrench::{ImageOptions, Wrench, WrenchThing, font_ascent, layout_simple_ascii};
+use {DEFAULT_BACKGROUND_COLOR, WHITE_COLOR, PLATFORM_DEFAULT_FACE_NAME};
+
+/// The largest number of copies a `repeat` directive may produce.
+const MAX_REPEAT_COUNT: i64 = 100000;
+
+/// The largest number of filters a stacking context may have.
+const MAX_FILTER_COUNT: usize = 32;
+
+/// The largest number of cells a checkerboard may be made of.
+const MAX_CHECKERBOARD_CELLS: i64 = 10000;
+
+/// The largest number of tiles an image's `tile_size` may split it into.
+const MAX_IMAGE_TILES: u64 = 4096;
+
+/// The default number of complex regions a single clip may have before
+/// `max_complex_clips` reports it.
+const DEFAULT_MAX_COMPLEX_CLIPS: usize = 256;
+
+/// Offsets the first two values of a point or rect, keeping any others.
+fn translate_values(values: &Yaml, offset: LayoutPoint) -> Yaml {
+    let mut nums = values.as_vec_f32().unwrap();
+    if nums.len() < 2 {
+        panic!("expected a point or rect to translate, got '{:?}'", values);
+    }
+    nums[0] += offset.x;
+    nums[1] += offset.y;
+    Yaml::Array(nums.iter().map(|v| Yaml::Real(v.to_string())).collect())
+}
+
+/// Offsets each of `keys` in `hash` that holds a point or rect.
+fn translate_keys(hash: &mut Table, keys: &[&str], offset: LayoutPoint) {
+    for key in keys {
+        let key = Yaml::String(key.to_string());
+        let translated = match hash.get(&key) {
+            Some(values) => translate_values(values, offset),
+            None => continue,
+        };
+        hash.insert(key, translated);
+    }
+}
+
+/// Offsets one of a clip's complex regions, which is either a rect with
+/// radii given as a list of numbers or a `{ rect, radii }` map.
+fn translate_complex_clip(clip: &Yaml, offset: LayoutPoint) -> Yaml {
+    match *clip {
+        Yaml::Hash(ref hash) => {
+            let mut hash = hash.clone();
+            translate_keys(&mut hash, &["rect"], offset);
+            Yaml::Hash(hash)
+        }
+        _ => translate_values(clip, offset),
+    }
+}
+
+/// Offsets each of `keys` in the map under `key` in `hash`, if there is one.
+fn translate_nested_keys(hash: &mut Table, key: &str, keys: &[&str], offset: LayoutPoint) {
+    let key = Yaml::String(key.to_owned());
+    let translated = match hash.get(&key) {
+        Some(&Yaml::Hash(ref nested)) => {
+            let mut nested = nested.clone();
+            translate_keys(&mut nested, keys, offset);
+            Yaml::Hash(nested)
+        }
+        _ => return,
+    };
+    hash.insert(key, translated);
+}
+
+/// Returns a copy of an item with its geometry translated by `offset`.
+/// Every key holding a position is moved: the item's rect, bounds, origin,
+/// overflow and tile_origin, a gradient's start and end, and the clip's
+/// rects, including exclude, image_mask and complex rects. Glyph offsets are
+/// relative to the origin, so glyphs without one are given one. The contents
+/// of a stacking context are relative to its bounds, so they move along with
+/// it.
+fn translate_item(item: &Yaml, offset: LayoutPoint) -> Yaml {
+    let mut hash = match *item {
+        Yaml::Hash(ref hash) => hash.clone(),
+        _ => panic!("expected an item, got '{:?}'", item),
+    };
+
+    translate_keys(&mut hash, &["rect", "bounds", "origin", "overflow", "tile_origin"], offset);
+    let origin_key = Yaml::String("origin".to_owned());
+    if hash.contains_key(&Yaml::String("glyphs".to_owned())) && !hash.contains_key(&origin_key) {
+        let origin = vec![Yaml::Real(offset.x.to_string()), Yaml::Real(offset.y.to_string())];
+        hash.insert(origin_key, Yaml::Array(origin));
+    }
+    translate_nested_keys(&mut hash, "gradient", &["start", "end"], offset);
+
+    let clip_key = Yaml::String("clip".to_owned());
+    let translated_clip = match hash.get(&clip_key) {
+        Some(&Yaml::Array(ref clips)) => {
+            Some(Yaml::Array(clips.iter().map(|clip| translate_complex_clip(clip, offset)).collect()))
+        }
+        Some(&Yaml::Hash(ref clip)) => {
+            let mut clip = clip.clone();
+            translate_keys(&mut clip, &["exclude", "rect"], offset);
+            translate_nested_keys(&mut clip, "image_mask", &["rect"], offset);
+            let complex_key = Yaml::String("complex".to_owned());
+            let translated_complex = match clip.get(&complex_key) {
+                Some(&Yaml::Array(ref complex)) => {
+                    Some(Yaml::Array(complex.iter().map(|c| translate_complex_clip(c, offset)).collect()))
+                }
+                _ => None,
+            };
+            if let Some(complex) = translated_complex {
+                clip.insert(complex_key, complex);
+            }
+            Some(Yaml::Hash(clip))
+        }
+        // none and parent aren't rects, so they're left as they are
+        Some(clip) if clip.as_str().is_some() && clip.as_str() != Some("none") &&
+                      clip.as_str() != Some("parent") => {
+            Some(translate_values(clip, offset))
+        }
+        _ => None,
+    };
+    if let Some(clip) = translated_clip {
+        hash.insert(clip_key, clip);
+    }
+
+    Yaml::Hash(hash)
+}
+
+/// Returns a copy of an item with its clip rect, and any exclude rect, mapped
+/// by `transform`. The clip stays an axis-aligned rect, so under a rotation
+/// or skew it becomes the bounding box of the mapped rect.
+fn transform_item_clip(item: &Yaml, transform: &LayoutTransform) -> Yaml {
+    let transform_rect = |rect: &Yaml| -> Yaml {
+        let rect = rect.as_rect().expect(&format!("expected a clip rect, got '{:?}'", rect));
+        let rect = LayoutRect::from_points(&[transform.transform_point(&rect.origin),
+                                             transform.transform_point(&rect.top_right()),
+                                             transform.transform_point(&rect.bottom_left()),
+                                             transform.transform_point(&rect.bottom_right())]);
+        let values = [rect.origin.x, rect.origin.y, rect.size.width, rect.size.height];
+        Yaml::Array(values.iter().map(|v| Yaml::Real(v.to_string())).collect())
+    };
+
+    let mut hash = match *item {
+        Yaml::Hash(ref hash) => hash.clone(),
+        _ => panic!("expected an item, got '{:?}'", item),
+    };
+    let clip_key = Yaml::String("clip".to_owned());
+    let transformed_clip = match hash.get(&clip_key) {
+        None => return item.clone(),
+        Some(clip) if clip.as_str() == Some("none") || clip.as_str() == Some("parent") => return item.clone(),
+        Some(&Yaml::Hash(ref clip)) => {
+            let mut clip = clip.clone();
+            for key in &["rect", "exclude"] {
+                let key = Yaml::String(key.to_string());
+                let transformed = match clip.get(&key) {
+                    Some(rect) => transform_rect(rect),
+                    None => continue,
+                };
+                clip.insert(key, transformed);
+            }
+            Yaml::Hash(clip)
+        }
+        Some(clip) => transform_rect(clip),
+    };
+    hash.insert(clip_key, transformed_clip);
+    Yaml::Hash(hash)
+}
+
+/// Reads the `capture_frame` key of a file, which defaults to the first frame
+/// and must be one of the `frame_count` frames the file holds.
+fn capture_frame_from_yaml(yaml: &Yaml, frame_count: usize) -> usize {
+    if yaml["capture_frame"].is_badvalue() {
+        return 0;
+    }
+    match yaml["capture_frame"].as_i64() {
+        Some(frame) if frame >= 0 && (frame as usize) < frame_count => frame as usize,
+        _ => panic!("capture_frame must be a frame number below {}, got {:?}", frame_count, yaml["capture_frame"]),
+    }
+}
+
+/// Finds the stacking context with the given `name` in the tree starting at
+/// `stacking_context`, which may be the match itself.
+fn find_named_stacking_context<'a>(stacking_context: &'a Yaml, name: &str) -> Option<&'a Yaml> {
+    if stacking_context["name"].as_str() == Some(name) {
+        return Some(stacking_context);
+    }
+    stacking_context["items"].as_vec().and_then(|items| {
+        items.iter()
+            .filter(|item| !item["stacking_context"].is_badvalue() ||
+                           item["type"].as_str() == Some("stacking_context"))
+            .filter_map(|item| find_named_stacking_context(item, name))
+            .next()
+    })
+}
+
+/// Collects the items under `items`, written in pipeline `pipeline_id`,
+/// that ask with `in_pipeline` to be built into a different pipeline. Such
+/// an item is moved along with everything inside it.
+fn collect_moved_items(items: &Yaml,
+                       pipeline_id: PipelineId,
+                       moved_items: &mut HashMap<PipelineId, Vec<Yaml>>) {
+    let items = match items.as_vec() {
+        Some(items) => items,
+        None => return,
+    };
+    for item in items {
+        if !item["in_pipeline"].is_badvalue() {
+            let target = item["in_pipeline"].as_pipeline_id()
+                .expect(&format!("invalid in_pipeline id '{:?}'", item["in_pipeline"]));
+            if target != pipeline_id {
+                moved_items.entry(target).or_insert(Vec::new()).push(item.clone());
+                continue;
+            }
+        }
+        collect_moved_items(&item["items"], pipeline_id, moved_items);
+    }
+}
+
+/// Reads a property binding id, which can be a string or an integer.
+fn as_binding_id(value: &Yaml) -> Option<String> {
+    match value.as_str() {
+        Some(id) => Some(id.to_owned()),
+        None => value.as_i64().map(|id| id.to_string()),
+    }
+}
+
+/// Reads a single value for every side, or a `[top, right, bottom, left]`
+/// array as in CSS.
+fn as_sides(value: &Yaml) -> Option<(f32, f32, f32, f32)> {
+    match value.as_force_f32() {
+        Some(v) => Some((v, v, v, v)),
+        None => match value.as_vec_f32() {
+            Some(ref v) if v.len() == 4 => Some((v[0], v[1], v[2], v[3])),
+            _ => None,
+        },
+    }
+}
+
+/// Insets `bounds` by a single value on every side, or by a `[top, right,
+/// bottom, left]` array.
+fn inset_rect(bounds: &LayoutRect, inset: &Yaml) -> LayoutRect {
+    let (top, right, bottom, left) = as_sides(inset)
+        .expect(&format!("clip inset must be a number or [top, right, bottom, left], got {:?}", inset));
+    if left + right > bounds.size.width || top + bottom > bounds.size.height {
+        panic!("clip inset {:?} is larger than the item bounds {:?}", inset, bounds);
+    }
+    LayoutRect::new(LayoutPoint::new(bounds.origin.x + left, bounds.origin.y + top),
+                    LayoutSize::new(bounds.size.width - left - right,
+                                    bounds.size.height - top - bottom))
+}
+
+/// Returns the size of the tiles a border image edge of `length` is drawn
+/// with, given the size of a tile at the edge's scale and a CSS
+/// border-image-repeat mode.
+fn border_image_tile_length(mode: &str, length: f32, scaled_tile_length: f32) -> f32 {
+    match mode {
+        "stretch" => length,
+        "repeat" => scaled_tile_length,
+        "round" => length / (length / scaled_tile_length).round().max(1.0),
+        _ => panic!("border_image repeat_mode can be stretch, repeat, or round -- got {}", mode),
+    }
+}
+
+/// Returns the items of a stacking context in the order they should be
+/// pushed. If any item has an integer `order`, the items are stably sorted by
+/// it. An item without an `order` takes the order of the closest item before
+/// it that has one, or 0 if there's none, so it stays next to that item.
+/// Background fills come before everything else, like a pipeline's
+/// background color.
+fn paint_order(items: &[Yaml]) -> Vec<&Yaml> {
+    let mut ordered: Vec<(i64, &Yaml)> = Vec::with_capacity(items.len());
+    let mut current_order = 0;
+    for item in items {
+        if item["type"].as_str() == Some("background_fill") {
+            ordered.push((i64::min_value(), item));
+            continue;
+        }
+        if !item["order"].is_badvalue() {
+            current_order = item["order"].as_i64()
+                .expect(&format!("item order must be an integer, got {:?}", item["order"]));
+        }
+        ordered.push((current_order, item));
+    }
+    // sort_by_key is stable, so ties keep their document order
+    ordered.sort_by_key(|&(order, _)| order);
+    ordered.into_iter().map(|(_, item)| item).collect()
+}
+
+/// The seed used when a document doesn't give one.
+const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// A small xorshift64* generator for randomized documents. It only depends on
+/// the seed, so the same seed always produces the same sequence and output.
+struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    fn new(seed: u64) -> SeededRng {
+        // xorshift gets stuck at zero, so map a zero seed to a fixed value
+        SeededRng { state: if seed == 0 { DEFAULT_SEED } else { seed } }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Returns a value in [0, 1).
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn next_color(&mut self) -> ColorF {
+        ColorF::new(self.next_f32(), self.next_f32(), self.next_f32(), 1.0)
+    }
+}
+
+/// Returns the smallest rect containing the origins of all the glyphs.
+fn glyph_bounds(glyphs: &[GlyphInstance]) -> LayoutRect {
+    if glyphs.is_empty() {
+        return LayoutRect::zero();
+    }
+
+    let mut min = LayoutPoint::new(glyphs[0].x, glyphs[0].y);
+    let mut max = min;
+    for glyph in glyphs {
+        min.x = min.x.min(glyph.x);
+        min.y = min.y.min(glyph.y);
+        max.x = max.x.max(glyph.x);
+        max.y = max.y.max(glyph.y);
+    }
+    LayoutRect::new(min, LayoutSize::new(max.x - min.x, max.y - min.y))
+}
 
 pub struct YamlFrameReader {
     frame_built: bool,
     yaml_path: PathBuf,
     aux_dir: PathBuf,
     frame_count: u32,
+    /// The entry of a `sequence` file that's being shown, and the number of
+    /// entries. A plain document is a sequence of one.
+    sequence_index: usize,
+    sequence_length: usize,
+    /// The sequence entry whose output a reftest compares, from the file's
+    /// `capture_frame` key.
+    capture_frame: usize,
+    /// The epoch given by the current document, if any. Otherwise the frame
+    /// count is used.
+    document_epoch: Option<u32>,
 
     builder: Option<DisplayListBuilder>,
+    /// Display lists for the document's non-root pipelines.
+    pipeline_builders: Vec<DisplayListBuilder>,
+    /// The order in which pipelines' display lists are sent to WebRender.
+    submit_order: Vec<PipelineId>,
+    /// The background color each pipeline is submitted with, if any.
+    background_colors: HashMap<PipelineId, ColorF>,
+    /// An image to paint beneath the root stacking context's items.
+    root_background_image: Option<PathBuf>,
+    /// A transform applied to the root stacking context after its own.
+    root_transform: Option<LayoutTransform>,
+    /// Items written elsewhere in the document that are built at the end of
+    /// the given pipeline's root stacking context.
+    moved_items: HashMap<PipelineId, Vec<Yaml>>,
+    /// The bounds of each stacking context being built, in its own
+    /// coordinate space, innermost last. Used by `clip: parent`.
+    stacking_context_bounds: Vec<LayoutRect>,
+    /// The transform from each stacking context being built to the space
+    /// it's placed in, innermost last. Used by `clip_space: parent`.
+    stacking_context_transforms: Vec<LayoutTransform>,
+    /// The overflow rect of the pipeline root being built, in the root's
+    /// local space.
+    pipeline_overflow: Option<LayoutRect>,
+    /// The `subpixel_bg_color` of each enclosing stacking context that sets
+    /// one, innermost last.
+    subpixel_bg_colors: Vec<ColorF>,
+    /// The value of each color binding for the current document.
+    color_bindings: HashMap<String, ColorF>,
+    /// The file and options of the image registered for each `image_id` tag
+    /// used by image items, and its key and size.
+    image_ids: HashMap<String, (PathBuf, ImageOptions, ImageKey, LayoutSize)>,
 
     queue_depth: u32,
+    dump_scene: bool,
+    bench_iterations: Option<u32>,
+    bench_warmup: bool,
+    /// The `name` of the stacking context to build instead of the root.
+    only_stacking_context: Option<String>,
+    /// When set, stepping past either end of a sequence wraps around
+    /// instead of stopping there.
+    loop_sequence: bool,
+
+    /// When false, glyph origins are rounded to whole pixels before pushing.
+    subpixel_positioning: bool,
+    /// If set, every item is outlined with a border of this color.
+    debug_outline_color: Option<ColorF>,
+    /// If set, text items are measured and their metrics printed instead of
+    /// being drawn.
+    measure_text: bool,
+    /// Draws values for randomized items, seeded by the document's `seed`.
+    rng: SeededRng,
+    /// If set, gradient stop colors are premultiplied by their alpha before
+    /// they're passed to the builder.
+    premultiply_gradient_stops: bool,
+    /// The number of complex regions a clip may have, and whether going
+    /// over it panics rather than warns.
+    max_complex_clips: usize,
+    panic_on_max_complex_clips: bool,
 }
 
 impl YamlFrameReader {
@@ -32,10 +433,39 @@ impl YamlFrameReader {
             yaml_path: yaml_path.to_owned(),
             aux_dir: yaml_path.parent().unwrap().to_owned(),
             frame_count: 0,
+            sequence_index: 0,
+            sequence_length: 1,
+            capture_frame: 0,
+            document_epoch: None,
 
             builder: None,
+            pipeline_builders: Vec::new(),
+            submit_order: Vec::new(),
+            background_colors: HashMap::new(),
+            image_ids: HashMap::new(),
+            color_bindings: HashMap::new(),
+            stacking_context_bounds: Vec::new(),
+            stacking_context_transforms: Vec::new(),
+            pipeline_overflow: None,
+            subpixel_bg_colors: Vec::new(),
+            root_background_image: None,
+            root_transform: None,
+            moved_items: HashMap::new(),
 
             queue_depth: 1,
+            dump_scene: false,
+            bench_iterations: None,
+            bench_warmup: false,
+            only_stacking_context: None,
+            loop_sequence: false,
+
+            subpixel_positioning: true,
+            debug_outline_color: None,
+            rng: SeededRng::new(DEFAULT_SEED),
+            measure_text: false,
+            premultiply_gradient_stops: false,
+            max_complex_clips: DEFAULT_MAX_COMPLEX_CLIPS,
+            panic_on_max_complex_clips: false,
         }
     }
 
@@ -44,14 +474,114 @@ impl YamlFrameReader {
 
         let mut y = YamlFrameReader::new(&yaml_file);
         y.queue_depth = args.value_of("queue").map(|s| s.parse::<u32>().unwrap()).unwrap_or(1);
+        y.dump_scene = args.is_present("dump-scene");
+        y.bench_iterations = args.value_of("bench").map(|s| {
+            let iterations = s.parse::<u32>().expect("bench expects a number of iterations");
+            if iterations == 0 {
+                panic!("bench expects at least one iteration");
+            }
+            iterations
+        });
+        y.bench_warmup = args.is_present("warmup");
+        y.only_stacking_context = args.value_of("only").map(|name| name.to_owned());
+        y.loop_sequence = args.is_present("loop");
         y
     }
 
+    fn rebuild(&mut self, wrench: &mut Wrench) {
+        self.reset_builders(wrench);
+        self.build(wrench);
+    }
+
+    /// Builds the display lists of every document in the file, as they'd be
+    /// built to show them, without submitting or rendering them.
+    pub fn validate(&mut self, wrench: &mut Wrench) {
+        loop {
+            self.rebuild(wrench);
+            if self.is_last_frame() {
+                break;
+            }
+            let next_index = self.sequence_index + 1;
+            self.set_sequence_index(next_index);
+        }
+    }
+
+    fn reset_builders(&mut self, wrench: &Wrench) {
+        self.builder = Some(DisplayListBuilder::new(wrench.root_pipeline_id));
+        self.pipeline_builders.clear();
+    }
+
+    fn submit(&mut self, wrench: &mut Wrench) {
+        for pipeline_id in &self.submit_order {
+            let builder = if *pipeline_id == wrench.root_pipeline_id {
+                self.builder.as_ref().unwrap()
+            } else {
+                self.pipeline_builders.iter().find(|builder| builder.pipeline_id == *pipeline_id).unwrap()
+            };
+            let background_color = self.background_colors.get(pipeline_id).cloned();
+            let epoch = self.document_epoch.unwrap_or(self.frame_count);
+            wrench.send_lists(epoch, builder.clone(), background_color);
+        }
+    }
+
+    /// Builds (if rebuilding display lists) and submits the document
+    /// `iterations` times, then prints the min, median and max wall-clock
+    /// time in milliseconds on a single `bench` line. The file is parsed
+    /// once up front, so only display list construction and submission are
+    /// timed.
+    fn run_benchmark(&mut self, wrench: &mut Wrench, iterations: u32) {
+        let yaml = self.load_document();
+
+        // Building the first frame rasterizes every glyph the document uses,
+        // so with warmup that frame is built before timing starts.
+        if self.bench_warmup {
+            self.frame_count += 1;
+            self.submit(wrench);
+            wrench.flush_backend();
+        }
+
+        let mut times = Vec::with_capacity(iterations as usize);
+        for _ in 0..iterations {
+            let start = time::SteadyTime::now();
+            if wrench.should_rebuild_display_lists() {
+                self.reset_builders(wrench);
+                self.build_document(wrench, &yaml);
+            }
+            self.frame_count += 1;
+            self.submit(wrench);
+            // wait for the backend to process the lists, so that's timed too
+            wrench.flush_backend();
+            times.push((time::SteadyTime::now() - start).num_microseconds().unwrap());
+        }
+
+        times.sort();
+        let as_ms = |us: i64| us as f64 / 1000.;
+        println!("bench iterations={} min_ms={:.3} median_ms={:.3} max_ms={:.3}",
+                 iterations, as_ms(times[0]), as_ms(times[times.len() / 2]), as_ms(times[times.len() - 1]));
+    }
+
     pub fn builder<'a>(&'a mut self) -> &'a mut DisplayListBuilder {
         self.builder.as_mut().unwrap()
     }
 
     pub fn build(&mut self, wrench: &mut Wrench) {
+        let yaml = self.load_document();
+        self.build_document(wrench, &yaml);
+
+        if self.dump_scene {
+            self.dump_built_scene();
+        }
+
+        // assert_max_bytes fails the test if any pipeline's serialized display
+        // list, including its auxiliary lists, grows beyond the given size.
+        if let Some(max_bytes) = yaml["assert_max_bytes"].as_i64() {
+            self.check_display_list_sizes(max_bytes as usize);
+        }
+    }
+
+    /// Reads the YAML file and returns the document for the current frame,
+    /// which is the sequence document at `sequence_index` if it's a sequence.
+    fn load_document(&mut self) -> Yaml {
         let mut file = File::open(&self.yaml_path).unwrap();
         let mut src = String::new();
         file.read_to_string(&mut src).unwrap();
@@ -59,22 +589,508 @@ impl YamlFrameReader {
         let mut yaml_doc = YamlLoader::load_from_str(&src).expect("Failed to parse YAML file");
         assert!(yaml_doc.len() == 1);
 
+        // A file can also hold a `sequence` of documents, each shown as a
+        // successive frame; next_frame and prev_frame step through them.
         let yaml = yaml_doc.pop().unwrap();
+        if !yaml["sequence"].is_badvalue() {
+            let documents = yaml["sequence"].as_vec().expect("sequence must be an array of documents");
+            if documents.is_empty() {
+                panic!("sequence must contain at least one document");
+            }
+            for (index, document) in documents.iter().enumerate() {
+                if document["root"].is_badvalue() {
+                    panic!("sequence document {} is missing a root stacking context", index);
+                }
+                if !document["epoch"].is_badvalue() && document["epoch"].as_i64().is_none() {
+                    panic!("sequence document {} has a non-integer epoch {:?}", index, document["epoch"]);
+                }
+            }
+            self.sequence_length = documents.len();
+            self.sequence_index %= self.sequence_length;
+            self.capture_frame = capture_frame_from_yaml(&yaml, self.sequence_length);
+            documents[self.sequence_index].clone()
+        } else {
+            self.sequence_length = 1;
+            self.capture_frame = capture_frame_from_yaml(&yaml, self.sequence_length);
+            yaml
+        }
+    }
+
+    /// Builds the display lists of every pipeline in `yaml`, a document
+    /// returned by `load_document`.
+    fn build_document(&mut self, wrench: &mut Wrench, yaml: &Yaml) {
         if yaml["root"].is_badvalue() {
             panic!("Missing root stacking context");
         }
-        self.add_stacking_context_from_yaml(wrench, &yaml["root"]);
+        self.document_epoch = yaml["epoch"].as_i64().map(|epoch| epoch as u32);
+        self.measure_text = yaml["measure_text"].as_bool().unwrap_or(false);
+        self.subpixel_positioning = yaml["subpixel_positioning"].as_bool().unwrap_or(true);
+        self.rng = SeededRng::new(yaml["seed"].as_i64().map_or(DEFAULT_SEED, |seed| seed as u64));
+        // WebRender takes straight alpha gradient stops. With premultiplied,
+        // each stop's r, g and b are multiplied by its alpha first, so the
+        // colors are interpolated as if they were premultiplied.
+        self.premultiply_gradient_stops = match yaml["gradient_alpha_space"].as_str() {
+            Some("straight") | None => false,
+            Some("premultiplied") => true,
+            Some(space) => panic!("gradient_alpha_space can be straight or premultiplied -- got {}", space),
+        };
+        // A clip with more than max_complex_clips complex regions is reported,
+        // with a warning or, with on_max_complex_clips: panic, a failure.
+        self.max_complex_clips = yaml["max_complex_clips"].as_i64()
+            .map_or(DEFAULT_MAX_COMPLEX_CLIPS, |max| max as usize);
+        self.panic_on_max_complex_clips = match yaml["on_max_complex_clips"].as_str() {
+            Some("warn") | None => false,
+            Some("panic") => true,
+            Some(action) => panic!("on_max_complex_clips can be warn or panic -- got {}", action),
+        };
+        self.debug_outline_color = if yaml["debug_outlines"].as_bool().unwrap_or(false) {
+            Some(yaml["debug_outline_color"].as_colorf().unwrap_or(ColorF::new(1.0, 0.0, 1.0, 1.0)))
+        } else {
+            None
+        };
+
+        // clear_color is what the framebuffer is cleared to before anything is
+        // drawn. It only shows through where no background_color is painted,
+        // e.g. with a transparent background_color.
+        wrench.set_clear_color(yaml["clear_color"].as_colorf().unwrap_or(*WHITE_COLOR));
+
+        // The root background is either a solid color, which WebRender paints
+        // itself, or an image stretched over the root stacking context.
+        let root_pipeline_id = self.builder().pipeline_id;
+        self.background_colors.clear();
+        self.image_ids.clear();
+        // bindings gives the values of this document's color_bindings, so a
+        // sequence can animate them from frame to frame.
+        self.color_bindings.clear();
+        if let Some(bindings) = yaml["bindings"].as_hash() {
+            for (id, value) in bindings {
+                let id = as_binding_id(id).expect(&format!("invalid binding id '{:?}'", id));
+                let color = value.as_colorf().expect(&format!("binding {} must be a color", id));
+                self.color_bindings.insert(id, color);
+            }
+        }
+        if !yaml["background_image"].is_badvalue() {
+            if !yaml["background_color"].is_badvalue() {
+                panic!("document can't have both a background_color and a background_image");
+            }
+            let mut file = self.aux_dir.clone();
+            file.push(yaml["background_image"].as_str().unwrap());
+            self.root_background_image = Some(file);
+        } else {
+            let background_color = yaml["background_color"].as_colorf().unwrap_or(*DEFAULT_BACKGROUND_COLOR);
+            self.background_colors.insert(root_pipeline_id, background_color);
+        }
+        // in_pipeline moves an item into another pipeline's root stacking
+        // context, after that pipeline's own items.
+        self.moved_items.clear();
+        collect_moved_items(&yaml["root"]["items"], root_pipeline_id, &mut self.moved_items);
+        let mut pipeline_ids = vec![root_pipeline_id];
+        if let Some(pipelines) = yaml["pipelines"].as_vec() {
+            for pipeline in pipelines {
+                if let Some(pipeline_id) = pipeline["id"].as_pipeline_id() {
+                    collect_moved_items(&pipeline["items"], pipeline_id, &mut self.moved_items);
+                    pipeline_ids.push(pipeline_id);
+                }
+            }
+        }
+        for pipeline_id in self.moved_items.keys() {
+            if !pipeline_ids.contains(pipeline_id) {
+                panic!("in_pipeline refers to undefined pipeline {:?}", pipeline_id);
+            }
+        }
+
+        // --only builds just the stacking context with that name as the root
+        let root = match self.only_stacking_context {
+            Some(ref name) => match find_named_stacking_context(&yaml["root"], name) {
+                Some(stacking_context) => stacking_context,
+                None => {
+                    println!("Warning: no stacking context named {}, building the whole document", name);
+                    &yaml["root"]
+                }
+            },
+            None => &yaml["root"],
+        };
+        // opacity fades the whole document by wrapping the root stacking
+        // context in one with an opacity filter, which composes with any
+        // filters of the stacking contexts inside it.
+        let opacity = yaml["opacity"].as_force_f32().unwrap_or(1.0);
+        if opacity < 0.0 || opacity > 1.0 {
+            panic!("document opacity must be between 0 and 1, got {}", opacity);
+        }
+        if opacity < 1.0 {
+            let bounds = LayoutRect::new(LayoutPoint::new(0.0, 0.0), wrench.window_size_f32());
+            let builder = self.builder();
+            let clip = builder.new_clip_region(&bounds, Vec::new(), None);
+            builder.push_stacking_context(ScrollPolicy::Scrollable,
+                                          bounds,
+                                          clip,
+                                          0,
+                                          &LayoutTransform::identity(),
+                                          &LayoutTransform::identity(),
+                                          MixBlendMode::Normal,
+                                          vec![FilterOp::Opacity(opacity)]);
+        }
+        // root_transform models a zoom or pan applied by the embedder, on top
+        // of the root stacking context's own transform.
+        self.root_transform = yaml["root_transform"].as_matrix4d();
+        self.add_stacking_context_from_yaml(wrench, root);
+        if opacity < 1.0 {
+            self.builder().pop_stacking_context();
+        }
+
+        // Other pipelines are described like the root stacking context, plus
+        // an id and optional background_color, and can be referenced by
+        // iframe items.
+        let mut defined_pipelines = vec![root_pipeline_id];
+        if !yaml["pipelines"].is_badvalue() {
+            for pipeline in yaml["pipelines"].as_vec().expect("pipelines must be an array") {
+                let pipeline_id = pipeline["id"].as_pipeline_id().expect("pipeline requires an id");
+                if defined_pipelines.contains(&pipeline_id) {
+                    panic!("pipeline {:?} is defined more than once", pipeline_id);
+                }
+                defined_pipelines.push(pipeline_id);
+                if let Some(background_color) = pipeline["background_color"].as_colorf() {
+                    self.background_colors.insert(pipeline_id, background_color);
+                }
+
+                let root_builder = mem::replace(&mut self.builder, Some(DisplayListBuilder::new(pipeline_id)));
+                self.root_transform = pipeline["root_transform"].as_matrix4d();
+                self.add_stacking_context_from_yaml(wrench, pipeline);
+                let pipeline_builder = mem::replace(&mut self.builder, root_builder);
+                self.pipeline_builders.push(pipeline_builder.unwrap());
+            }
+        }
+
+        // Pipelines are submitted root first and then in definition order,
+        // unless the document gives an explicit order covering all of them.
+        self.submit_order = if yaml["submit_order"].is_badvalue() {
+            defined_pipelines
+        } else {
+            let submit_order: Vec<PipelineId> = yaml["submit_order"].as_vec()
+                .expect("submit_order must be an array of pipeline ids")
+                .iter()
+                .map(|id| id.as_pipeline_id().expect(&format!("invalid pipeline id '{:?}'", id)))
+                .collect();
+            for pipeline_id in &defined_pipelines {
+                if submit_order.iter().filter(|id| *id == pipeline_id).count() != 1 {
+                    panic!("submit_order must list pipeline {:?} exactly once", pipeline_id);
+                }
+            }
+            if submit_order.len() != defined_pipelines.len() {
+                panic!("submit_order {:?} lists pipelines that aren't defined", submit_order);
+            }
+            submit_order
+        };
+    }
+
+    fn check_display_list_sizes(&self, max_bytes: usize) {
+        let builders = self.builder.iter().chain(self.pipeline_builders.iter());
+        for builder in builders {
+            let (display_list, auxiliary_lists) = builder.clone().finalize();
+            let bytes = display_list.descriptor().size() + auxiliary_lists.descriptor().size();
+            println!("pipeline {:?}: {} display list bytes (max {})", builder.pipeline_id, bytes, max_bytes);
+            if bytes > max_bytes {
+                panic!("pipeline {:?} display list is {} bytes, over assert_max_bytes {}",
+                       builder.pipeline_id, bytes, max_bytes);
+            }
+        }
+    }
+
+    /// Prints the built display list of each pipeline, the root first, with
+    /// stacking contexts indented by nesting depth, followed by a count of
+    /// each kind of item in that pipeline.
+    fn dump_built_scene(&self) {
+        let builders = self.builder.iter().chain(self.pipeline_builders.iter());
+        for builder in builders {
+            println!("pipeline {:?}: {} items", builder.pipeline_id, builder.list.len());
+
+            let mut counts: Vec<(&'static str, usize)> = Vec::new();
+            let mut depth = 0;
+            for item in &builder.list {
+                let name = match item.item {
+                    SpecificDisplayItem::Rectangle(..) => "rect",
+                    SpecificDisplayItem::Text(..) => "text",
+                    SpecificDisplayItem::Image(..) => "image",
+                    SpecificDisplayItem::YuvImage(..) => "yuv_image",
+                    SpecificDisplayItem::WebGL(..) => "webgl",
+                    SpecificDisplayItem::Border(..) => "border",
+                    SpecificDisplayItem::BoxShadow(..) => "box_shadow",
+                    SpecificDisplayItem::Gradient(..) => "gradient",
+                    SpecificDisplayItem::Iframe(..) => "iframe",
+                    SpecificDisplayItem::PushStackingContext(..) => "stacking_context",
+                    SpecificDisplayItem::PopStackingContext => {
+                        depth -= 1;
+                        continue;
+                    }
+                    SpecificDisplayItem::PushScrollLayer(..) => "scroll_layer",
+                    SpecificDisplayItem::PopScrollLayer => {
+                        depth -= 1;
+                        continue;
+                    }
+                };
+
+                match item.item {
+                    SpecificDisplayItem::PushStackingContext(ref sc) => {
+                        println!("{:indent$}stacking_context {:?} z_index {}", "",
+                                 sc.stacking_context.bounds, sc.stacking_context.z_index, indent = depth * 2);
+                        depth += 1;
+                    }
+                    SpecificDisplayItem::PushScrollLayer(..) => {
+                        println!("{:indent$}scroll_layer {:?}", "", item.rect, indent = depth * 2);
+                        depth += 1;
+                    }
+                    _ => println!("{:indent$}{} {:?}", "", name, item.rect, indent = depth * 2),
+                }
+
+                match counts.iter().position(|&(n, _)| n == name) {
+                    Some(index) => counts[index].1 += 1,
+                    None => counts.push((name, 1)),
+                }
+            }
+
+            for &(name, count) in &counts {
+                println!("{}: {}", name, count);
+            }
+        }
+    }
+
+    /// Resolves an item's `clip` key. An absent clip inherits `default_clip`,
+    /// `clip: none` resets to the full window regardless of what would be
+    /// inherited, and any other value is parsed as a clip region that replaces
+    /// the inherited one.
+    fn to_clip_region(&mut self,
+                      wrench: &mut Wrench,
+                      clip: &Yaml,
+                      item_bounds: &LayoutRect,
+                      default_clip: &ClipRegion)
+                      -> ClipRegion {
+        let clip_region = match clip.as_str() {
+            Some("none") => {
+                let win_rect = LayoutRect::new(LayoutPoint::new(0.0, 0.0), wrench.window_size_f32());
+                self.builder().new_clip_region(&win_rect, Vec::new(), None)
+            }
+            // unlike none, parent stays within the enclosing stacking context
+            Some("parent") => {
+                let parent_rect = *self.stacking_context_bounds.last()
+                    .expect("clip: parent used outside of a stacking context");
+                self.builder().new_clip_region(&parent_rect, Vec::new(), None)
+            }
+            // exclude clips are resolved by the caller into the default clip
+            _ if !clip["exclude"].is_badvalue() => *default_clip,
+            _ if !clip["image_mask"].is_badvalue() => self.to_masked_clip_region(wrench, clip),
+            _ if !clip["inset"].is_badvalue() => {
+                let rect = inset_rect(item_bounds, &clip["inset"]);
+                self.builder().new_clip_region(&rect, Vec::new(), None)
+            }
+            _ => clip.as_clip_region(self.builder()).unwrap_or(*default_clip),
+        };
+
+        if clip_region.complex.length > self.max_complex_clips {
+            let message = format!("clip of item at {:?} has {} complex regions, over max_complex_clips {}",
+                                  item_bounds, clip_region.complex.length, self.max_complex_clips);
+            if self.panic_on_max_complex_clips {
+                panic!("{}", message);
+            }
+            println!("Warning: {}", message);
+        }
+        clip_region
+    }
+
+    /// Builds a clip region from `{ rect, radii, complex, image_mask }`. The
+    /// mask is either an image file (`image`), or inline A8 data given as
+    /// `data` with `width`, `height`, an optional `stride` (default width)
+    /// and `format`. With `radii`, the rect is also a rounded clip, so the
+    /// region clips to both the rounded rect and the mask.
+    fn to_masked_clip_region(&mut self, wrench: &mut Wrench, clip: &Yaml) -> ClipRegion {
+        let mask = &clip["image_mask"];
+        let mask_rect = mask["rect"].as_rect().expect("image_mask must have a rect");
+
+        let mask_key = if !mask["image"].is_badvalue() {
+            let mut file = self.aux_dir.clone();
+            file.push(mask["image"].as_str().unwrap());
+            wrench.add_or_get_image(&file, ImageOptions::default()).0
+        } else {
+            let width = mask["width"].as_i64().expect("image_mask data requires a width") as u32;
+            let height = mask["height"].as_i64().expect("image_mask data requires a height") as u32;
+            let stride = mask["stride"].as_i64().map_or(width, |stride| stride as u32);
+            if stride < width {
+                panic!("image_mask stride {} must be at least the width {}", stride, width);
+            }
+            match mask["format"].as_str() {
+                Some("a8") | None => {}
+                Some(format) => panic!("image_mask data only supports the a8 format, got {}", format),
+            }
+            let data: Vec<u8> = mask["data"].as_vec_u32().expect("image_mask requires an image or data")
+                .iter().map(|v| {
+                    if *v > 255 {
+                        panic!("image_mask data values must be between 0 and 255, got {}", v);
+                    }
+                    *v as u8
+                }).collect();
+            if data.len() < (stride * height) as usize {
+                panic!("image_mask data has {} bytes, expected at least {} ({} stride x {} height)",
+                       data.len(), stride * height, stride, height);
+            }
+            wrench.api.add_image(width, height, Some(stride), ImageFormat::A8, ImageData::new(data))
+        };
+
+        let mut complex: Vec<ComplexClipRegion> = match clip["complex"].as_vec() {
+            Some(complex) => complex.iter().map(|c| c.as_complex_clip_rect().unwrap()).collect(),
+            None => Vec::new(),
+        };
+        let main = clip["rect"].as_rect().unwrap_or(mask_rect);
+        if let Some(radii) = clip["radii"].as_border_radius() {
+            complex.push(ComplexClipRegion::new(main, radii));
+        }
+        let image_mask = ImageMask {
+            image: mask_key,
+            rect: mask_rect,
+            repeat: mask["repeat"].as_bool().unwrap_or(false),
+        };
+        self.builder().new_clip_region(&main, complex, Some(image_mask))
+    }
+
+    /// Reads an item's `color`, or draws an opaque one from the seeded RNG if
+    /// the item has `random_color: true`.
+    fn color_from_yaml(&mut self, item: &Yaml) -> Option<ColorF> {
+        if item["random_color"].as_bool().unwrap_or(false) {
+            if !item["color"].is_badvalue() {
+                panic!("item can't have both a color and random_color: '{:?}'", item);
+            }
+            return Some(self.rng.next_color());
+        }
+        item["color"].as_colorf()
     }
 
     fn handle_rect(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml)
     {
         let rect = item[if item["type"].is_badvalue() { "rect" } else { "bounds" }]
             .as_rect().expect("rect type must have bounds");
-        let color = item["color"].as_colorf().unwrap_or(*WHITE_COLOR);
+        // The builder has no per-item antialiasing flag, so antialias: false
+        // snaps the rect's edges to whole pixels, which leaves nothing to
+        // antialias in an untransformed stacking context.
+        let rect = if item["antialias"].as_bool().unwrap_or(true) {
+            rect
+        } else {
+            let origin = LayoutPoint::new(rect.origin.x.round(), rect.origin.y.round());
+            LayoutRect::new(origin, LayoutSize::new(rect.max_x().round() - origin.x,
+                                                    rect.max_y().round() - origin.y))
+        };
+        let clip = self.to_clip_region(wrench, &item["clip"], &rect, clip_region);
+
+        // a gradient sub-map fills the rect with a gradient instead of a color
+        let gradient = &item["gradient"];
+        if !gradient.is_badvalue() {
+            if !item["color"].is_badvalue() {
+                panic!("rect can't have both a color and a gradient: '{:?}'", item);
+            }
+            let start = gradient["start"].as_point().expect("gradient must have a start point");
+            let end = gradient["end"].as_point().expect("gradient must have an end point");
+            let mut stops = gradient["stops"].as_gradient_stops().expect("gradient must have stops");
+            if self.premultiply_gradient_stops {
+                for stop in &mut stops {
+                    let a = stop.color.a;
+                    stop.color = ColorF::new(stop.color.r * a, stop.color.g * a, stop.color.b * a, a);
+                }
+            }
+            self.builder().push_gradient(rect, clip, start, end, stops);
+            return;
+        }
+
+        let color = self.color_from_yaml(item).unwrap_or(*WHITE_COLOR);
+        let color = match as_binding_id(&item["color_binding"]) {
+            Some(id) => self.resolve_color_binding(&id, color),
+            None => color,
+        };
+        self.builder().push_rect(rect, clip, color);
+    }
+
+    /// Returns the current value of a color binding. WebRender has no
+    /// animatable properties yet, so the value is resolved here when the
+    /// display list is built, and is `color` if the document doesn't bind it.
+    fn resolve_color_binding(&self, id: &str, color: ColorF) -> ColorF {
+        self.color_bindings.get(id).cloned().unwrap_or(color)
+    }
 
+    /// Handles `type: background_fill`, which paints a color over `bounds`,
+    /// by default the pipeline's overflow rect, beneath the other items of
+    /// the root stacking context, the way WebRender paints a pipeline's
+    /// background color. Like the background, it ignores inherited clips.
+    fn handle_background_fill(&mut self, item: &Yaml) {
+        if self.stacking_context_bounds.len() != 1 {
+            panic!("background_fill must be an item of a pipeline's root stacking context");
+        }
+        let bounds = match item["bounds"].as_rect() {
+            Some(bounds) => bounds,
+            None => self.pipeline_overflow.expect("background_fill without bounds needs the root's overflow rect"),
+        };
+        let color = self.color_from_yaml(item).expect("background_fill requires a color");
         let builder = self.builder();
-        let clip = item["clip"].as_clip_region(builder).unwrap_or(*clip_region);
-        builder.push_rect(rect, clip, color);
+        let clip = builder.new_clip_region(&bounds, Vec::new(), None);
+        builder.push_rect(bounds, clip, color);
+    }
+
+    /// Handles `type: checkerboard`, which covers `bounds` with a grid of
+    /// `cell_size` rects alternating between the two `colors`, starting with
+    /// the first at the top left. Cells on the right and bottom edges are cut
+    /// to the bounds. Each cell is pushed as a rect item, so rect keys such as
+    /// clip apply to every cell.
+    fn handle_checkerboard(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml)
+    {
+        let bounds = item["bounds"].as_rect().expect("checkerboard must have bounds");
+        let cell_size = match item["cell_size"].as_force_f32() {
+            Some(size) => LayoutSize::new(size, size),
+            None => item["cell_size"].as_size().expect("checkerboard must have a cell_size"),
+        };
+        if cell_size.width <= 0.0 || cell_size.height <= 0.0 {
+            panic!("checkerboard cell_size must be positive, got {:?}", item["cell_size"]);
+        }
+        let colors = item["colors"].as_vec()
+            .and_then(|colors| if colors.len() == 2 { Some(colors) } else { None })
+            .expect("checkerboard must have two colors");
+        for color in colors {
+            if color.as_colorf().is_none() {
+                panic!("checkerboard colors must be colors, got {:?}", color);
+            }
+        }
+        if !item["random_color"].is_badvalue() {
+            panic!("checkerboard can't have random_color, its cells take their colors from colors");
+        }
+
+        // Each dimension is checked on its own first, so the cell count
+        // can't overflow.
+        let columns = (bounds.size.width / cell_size.width).ceil() as i64;
+        let rows = (bounds.size.height / cell_size.height).ceil() as i64;
+        let cells = if columns <= MAX_CHECKERBOARD_CELLS && rows <= MAX_CHECKERBOARD_CELLS {
+            columns * rows
+        } else {
+            i64::max_value()
+        };
+        if cells > MAX_CHECKERBOARD_CELLS {
+            panic!("checkerboard has {} columns and {} rows of cells, more than the maximum of {} cells",
+                   columns, rows, MAX_CHECKERBOARD_CELLS);
+        }
+
+        let mut cell = match *item {
+            Yaml::Hash(ref hash) => hash.clone(),
+            _ => unreachable!(),
+        };
+        for key in &["type", "bounds", "cell_size", "colors"] {
+            cell.remove(&Yaml::String(key.to_string()));
+        }
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let x = bounds.origin.x + column as f32 * cell_size.width;
+                let y = bounds.origin.y + row as f32 * cell_size.height;
+                let width = cell_size.width.min(bounds.max_x() - x);
+                let height = cell_size.height.min(bounds.max_y() - y);
+                let rect = [x, y, width, height].iter().map(|v| Yaml::Real(v.to_string())).collect();
+                cell.insert(Yaml::String("rect".to_owned()), Yaml::Array(rect));
+                cell.insert(Yaml::String("color".to_owned()), colors[((row + column) % 2) as usize].clone());
+                self.handle_rect(wrench, clip_region, &Yaml::Hash(cell.clone()));
+            }
+        }
     }
 
     fn handle_image(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml)
@@ -82,7 +1098,52 @@ impl YamlFrameReader {
         let filename = item[if item["type"].is_badvalue() { "image" } else { "src" }].as_str().unwrap();
         let mut file = self.aux_dir.clone();
         file.push(filename);
-        let (image_key, image_dims) = wrench.add_or_get_image(&file);
+        // Image data is assumed to already be premultiplied, which is what
+        // WebRender expects; `premultiplied: false` makes wrench convert it.
+        let premultiplied = item["premultiplied"].as_bool().unwrap_or(true);
+        // src_rect selects a portion of the image, e.g. a sprite in a sheet;
+        // the item then behaves as if the image were just that portion.
+        let src_rect = item["src_rect"].as_rect().map(|rect| {
+            (rect.origin.x as u32, rect.origin.y as u32, rect.size.width as u32, rect.size.height as u32)
+        });
+        // color_key makes pixels of exactly this color (no tolerance) transparent
+        let color_key = item["color_key"].as_vec_f32().map(|rgb| {
+            if rgb.len() != 3 {
+                panic!("color_key expected 3 values, got '{:?}'", item["color_key"]);
+            }
+            (rgb[0] as u8, rgb[1] as u8, rgb[2] as u8)
+        });
+        // opaque: true tells WebRender the image has no transparent pixels.
+        // Without it, opacity is inferred from the pixels. A wrong hint shows
+        // whatever color the transparent pixels hold, which is useful for
+        // testing the opaque path.
+        let options = ImageOptions {
+            premultiply: !premultiplied,
+            src_rect: src_rect,
+            color_key: color_key,
+            opaque: item["opaque"].as_bool().unwrap_or(false),
+        };
+        // Items tagged with the same image_id all use the image key registered
+        // by the first of them, so they must name the same file with the same
+        // options. Different ids may still share a key, as items without an
+        // id do when they name the same file with the same options.
+        let (image_key, image_dims) = match item["image_id"].as_str() {
+            Some(image_id) => match self.image_ids.get(image_id).cloned() {
+                Some((id_file, id_options, image_key, image_dims)) => {
+                    if id_file != file || id_options != options {
+                        panic!("image_id {} is used for both {:?} with {:?} and {:?} with {:?}",
+                               image_id, id_file, id_options, file, options);
+                    }
+                    (image_key, image_dims)
+                }
+                None => {
+                    let (image_key, image_dims) = wrench.add_or_get_image(&file, options);
+                    self.image_ids.insert(image_id.to_owned(), (file.clone(), options, image_key, image_dims));
+                    (image_key, image_dims)
+                }
+            },
+            None => wrench.add_or_get_image(&file, options),
+        };
 
         let bounds_raws = item["bounds"].as_vec_f32().unwrap();
         let bounds = if bounds_raws.len() == 2 {
@@ -95,58 +1156,403 @@ impl YamlFrameReader {
             panic!("image expected 2 or 4 values in bounds, got '{:?}'", item["bounds"]);
         };
 
-        let clip = item["clip"].as_clip_region(self.builder.as_mut().unwrap())
-            .unwrap_or(*clip_region);
-        let stretch_size = item["stretch_size"].as_size()
-            .unwrap_or(image_dims);
-        let tile_spacing = item["tile_spacing"].as_size()
-            .unwrap_or(LayoutSize::new(0.0, 0.0));
+        let clip = self.to_clip_region(wrench, &item["clip"], &bounds, clip_region);
         let rendering = match item["rendering"].as_str() {
             Some("auto") | None => ImageRendering::Auto,
             Some("crisp_edges") => ImageRendering::CrispEdges,
             Some("pixelated") => ImageRendering::Pixelated,
             Some(_) => panic!("ImageRendering can be auto, crisp_edges, or pixelated -- got {:?}", item),
         };
+
+        // The backend has no tiled images, so tile_size splits the image into
+        // tiles of at most tile_size x tile_size pixels, each registered and
+        // pushed as its own image, drawn at the image's intrinsic size from
+        // the bounds origin.
+        if !item["tile_size"].is_badvalue() {
+            let tile_size = item["tile_size"].as_i64().unwrap_or(0);
+            if tile_size <= 0 {
+                panic!("image tile_size must be a positive integer, got {:?}", item["tile_size"]);
+            }
+            for key in &["stretch_size", "tile_spacing", "fit", "orientation", "flip_h", "flip_v", "image_id"] {
+                if !item[*key].is_badvalue() {
+                    panic!("image tile_size can't be combined with {}", key);
+                }
+            }
+            let tile_size = tile_size as u64;
+            let columns = (image_dims.width as u64 + tile_size - 1) / tile_size;
+            let rows = (image_dims.height as u64 + tile_size - 1) / tile_size;
+            if columns * rows > MAX_IMAGE_TILES {
+                panic!("image tile_size {} splits the image into {} tiles, more than the maximum of {}",
+                       tile_size, columns * rows, MAX_IMAGE_TILES);
+            }
+            // tile_size can be larger than the image, but not than a u32
+            let tile_size = tile_size.min(u32::max_value() as u64) as u32;
+            self.push_image_tiles(wrench, &file, options, &bounds, clip, rendering, tile_size);
+            return;
+        }
+
+        // fit sizes the image within the bounds like CSS object-fit: fill
+        // stretches it to the bounds, contain and cover scale it to fit inside
+        // or cover the bounds, and none keeps its intrinsic size. The image is
+        // then centered in the bounds and anything outside them is clipped.
+        let (bounds, clip, stretch_size) = match item["fit"].as_str() {
+            None => {
+                if !item["fit"].is_badvalue() {
+                    panic!("image fit must be fill, contain, cover, or none -- got {:?}", item["fit"]);
+                }
+                (bounds, clip, item["stretch_size"].as_size().unwrap_or(image_dims))
+            }
+            Some(fit) => {
+                if !item["stretch_size"].is_badvalue() {
+                    panic!("image can't have both a fit and a stretch_size: '{:?}'", item);
+                }
+                let x_scale = bounds.size.width / image_dims.width;
+                let y_scale = bounds.size.height / image_dims.height;
+                let fitted_size = match fit {
+                    "fill" => bounds.size,
+                    "contain" => image_dims * x_scale.min(y_scale),
+                    "cover" => image_dims * x_scale.max(y_scale),
+                    "none" => image_dims,
+                    _ => panic!("image fit must be fill, contain, cover, or none -- got {}", fit),
+                };
+                let fitted_origin = LayoutPoint::new(
+                    bounds.origin.x + (bounds.size.width - fitted_size.width) / 2.0,
+                    bounds.origin.y + (bounds.size.height - fitted_size.height) / 2.0);
+                let clip = if item["clip"].is_badvalue() {
+                    self.builder().new_clip_region(&bounds, Vec::new(), None)
+                } else {
+                    clip
+                };
+                (LayoutRect::new(fitted_origin, fitted_size), clip, fitted_size)
+            }
+        };
+        // tile_spacing may be a single value applied to both axes
+        let tile_spacing = match item["tile_spacing"].as_force_f32() {
+            Some(spacing) => LayoutSize::new(spacing, spacing),
+            None => item["tile_spacing"].as_size().unwrap_or(LayoutSize::new(0.0, 0.0)),
+        };
+
+        // tile_origin is a point the tile grid is aligned to, shifting the
+        // tile phase. WebRender always starts tiling at the bounds origin, so
+        // the bounds are extended up and to the left to the nearest grid
+        // point, i.e. by (origin - tile_origin) modulo the stretch_size plus
+        // tile_spacing, and the clip is cut back to the original bounds.
+        let (bounds, clip) = match item["tile_origin"].as_point() {
+            None => (bounds, clip),
+            Some(tile_origin) => {
+                if !item["fit"].is_badvalue() {
+                    panic!("image can't have both a fit and a tile_origin: '{:?}'", item);
+                }
+                let period = LayoutSize::new(stretch_size.width + tile_spacing.width,
+                                             stretch_size.height + tile_spacing.height);
+                if period.width <= 0.0 || period.height <= 0.0 {
+                    panic!("image tile_origin needs a positive stretch_size plus tile_spacing, got {:?}", period);
+                }
+                let phase = |offset: f32, period: f32| ((offset % period) + period) % period;
+                let shift = LayoutSize::new(phase(bounds.origin.x - tile_origin.x, period.width),
+                                            phase(bounds.origin.y - tile_origin.y, period.height));
+                let tiled_bounds = LayoutRect::new(
+                    LayoutPoint::new(bounds.origin.x - shift.width, bounds.origin.y - shift.height),
+                    LayoutSize::new(bounds.size.width + shift.width, bounds.size.height + shift.height));
+                let main = clip.main.intersection(&bounds).unwrap_or(LayoutRect::zero());
+                (tiled_bounds, ClipRegion { main: main, ..clip })
+            }
+        };
+
+        // orientation rotates the image clockwise about the center of its
+        // bounds, after flip_h and flip_v mirror it. This is done with a
+        // stacking context, so the item's clip is transformed along with it.
+        let orientation = item["orientation"].as_i64().unwrap_or(0);
+        if orientation != 0 && orientation != 90 && orientation != 180 && orientation != 270 {
+            panic!("image orientation must be 0, 90, 180, or 270, got {}", orientation);
+        }
+        let flip_h = item["flip_h"].as_bool().unwrap_or(false);
+        let flip_v = item["flip_v"].as_bool().unwrap_or(false);
+        let oriented = orientation != 0 || flip_h || flip_v;
+        if oriented {
+            let center = LayoutPoint::new(bounds.origin.x + bounds.size.width / 2.0,
+                                          bounds.origin.y + bounds.size.height / 2.0);
+            let transform = parse_css_transform(&format!(
+                "translate({}px, {}px) rotate({}deg) scale({}, {}) translate({}px, {}px)",
+                center.x, center.y, orientation,
+                if flip_h { -1 } else { 1 }, if flip_v { -1 } else { 1 },
+                -center.x, -center.y));
+            self.push_item_stacking_context(wrench, 0, &transform);
+        }
+
         self.builder().push_image(bounds, clip, stretch_size, tile_spacing, rendering, image_key);
+
+        if oriented {
+            self.builder().pop_stacking_context();
+        }
+    }
+
+    fn push_image_tiles(&mut self,
+                        wrench: &mut Wrench,
+                        file: &Path,
+                        options: ImageOptions,
+                        bounds: &LayoutRect,
+                        clip: ClipRegion,
+                        rendering: ImageRendering,
+                        tile_size: u32) {
+        for (tile_rect, tile_key) in wrench.add_or_get_image_tiles(file, options, tile_size) {
+            let tile_rect = tile_rect.translate(&bounds.origin);
+            // tiles start at the bounds origin, so clipping a tile to the
+            // bounds only ever cuts off its right or bottom edge
+            let tile_bounds = match tile_rect.intersection(bounds) {
+                Some(tile_bounds) => tile_bounds,
+                None => continue,
+            };
+            self.builder().push_image(tile_bounds, clip, tile_rect.size, LayoutSize::zero(), rendering, tile_key);
+        }
+    }
+
+    /// Handles `type: border_image`, a CSS border-image drawn as a nine-patch.
+    /// `slice` gives the insets, in image pixels, that cut the image into the
+    /// grid, and `widths` the border widths the edges are drawn at (default:
+    /// the slice). `repeat_mode` is stretch, repeat, or round, or a pair for
+    /// the horizontal and vertical edges. The center is only drawn with
+    /// `fill: true`. Each cell is pushed as its own image; repeated tiles
+    /// start at the cell's origin rather than being centered as in CSS.
+    fn handle_border_image(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml)
+    {
+        let mut file = self.aux_dir.clone();
+        file.push(item["src"].as_str().expect("border_image must have a src"));
+        let bounds = item["bounds"].as_rect().expect("border_image must have bounds");
+        let (_, image_size) = wrench.add_or_get_image(&file, ImageOptions::default());
+
+        let slice = as_sides(&item["slice"])
+            .expect("border_image slice must be a number or [top, right, bottom, left]");
+        if slice.1 + slice.3 > image_size.width || slice.0 + slice.2 > image_size.height {
+            panic!("border_image slice {:?} doesn't fit in the {:?} image", item["slice"], image_size);
+        }
+        let widths = if item["widths"].is_badvalue() {
+            slice
+        } else {
+            as_sides(&item["widths"])
+                .expect("border_image widths must be a number or [top, right, bottom, left]")
+        };
+        let (repeat_x, repeat_y) = match item["repeat_mode"].as_str() {
+            Some(mode) => (mode, mode),
+            None if item["repeat_mode"].is_badvalue() => ("stretch", "stretch"),
+            None => {
+                let modes = item["repeat_mode"].as_vec()
+                    .and_then(|modes| if modes.len() == 2 { Some(modes) } else { None })
+                    .expect("border_image repeat_mode must be a mode or a pair of modes");
+                (modes[0].as_str().unwrap(), modes[1].as_str().unwrap())
+            }
+        };
+        let fill = item["fill"].as_bool().unwrap_or(false);
+        let clip = self.to_clip_region(wrench, &item["clip"], &bounds, clip_region);
+
+        let src_x = [0.0, slice.3, image_size.width - slice.1, image_size.width];
+        let src_y = [0.0, slice.0, image_size.height - slice.2, image_size.height];
+        let dst_x = [bounds.origin.x, bounds.origin.x + widths.3, bounds.max_x() - widths.1, bounds.max_x()];
+        let dst_y = [bounds.origin.y, bounds.origin.y + widths.0, bounds.max_y() - widths.2, bounds.max_y()];
+
+        for row in 0..3 {
+            for col in 0..3 {
+                if row == 1 && col == 1 && !fill {
+                    continue;
+                }
+                let src_size = LayoutSize::new(src_x[col + 1] - src_x[col], src_y[row + 1] - src_y[row]);
+                let dst = LayoutRect::new(LayoutPoint::new(dst_x[col], dst_y[row]),
+                                          LayoutSize::new(dst_x[col + 1] - dst_x[col],
+                                                          dst_y[row + 1] - dst_y[row]));
+                if src_size.width <= 0.0 || src_size.height <= 0.0 || dst.size.width <= 0.0 || dst.size.height <= 0.0 {
+                    continue;
+                }
+
+                // Corners are stretched. Edges tile along their length at the
+                // scale that fits the slice to the border width; the center
+                // uses the scales of the top and left edges.
+                let scale_row = if row == 1 { 0 } else { row };
+                let scale_col = if col == 1 { 0 } else { col };
+                let x_scale = (dst_y[scale_row + 1] - dst_y[scale_row]) / (src_y[scale_row + 1] - src_y[scale_row]);
+                let y_scale = (dst_x[scale_col + 1] - dst_x[scale_col]) / (src_x[scale_col + 1] - src_x[scale_col]);
+                let x_scale = if x_scale.is_finite() { x_scale } else { 1.0 };
+                let y_scale = if y_scale.is_finite() { y_scale } else { 1.0 };
+                let stretch_size = LayoutSize::new(
+                    if col == 1 {
+                        border_image_tile_length(repeat_x, dst.size.width, src_size.width * x_scale)
+                    } else {
+                        dst.size.width
+                    },
+                    if row == 1 {
+                        border_image_tile_length(repeat_y, dst.size.height, src_size.height * y_scale)
+                    } else {
+                        dst.size.height
+                    });
+
+                let options = ImageOptions {
+                    src_rect: Some((src_x[col] as u32, src_y[row] as u32,
+                                    src_size.width as u32, src_size.height as u32)),
+                    ..ImageOptions::default()
+                };
+                let (image_key, _) = wrench.add_or_get_image(&file, options);
+                self.builder().push_image(dst, clip, stretch_size, LayoutSize::zero(),
+                                          ImageRendering::Auto, image_key);
+            }
+        }
+    }
+
+    /// Prints the ink metrics of a text item's glyphs as rasterized by
+    /// WebRender: the width and height of their bounding box, and its ascent
+    /// above and descent below the first glyph's baseline. If the item has
+    /// `expect_width` or `expect_height`, the measured value must be within
+    /// `expect_tolerance` (default 0.5) of it.
+    fn measure_text_item(&mut self,
+                         wrench: &mut Wrench,
+                         item: &Yaml,
+                         font_key: FontKey,
+                         size: Au,
+                         glyphs: &[GlyphInstance]) {
+        let bounds = wrench.glyph_ink_bounds(font_key, size, glyphs).unwrap_or(LayoutRect::zero());
+        let baseline = glyphs.first().map_or(0.0, |glyph| glyph.y);
+        let ascent = (baseline - bounds.origin.y).max(0.0);
+        let descent = (bounds.max_y() - baseline).max(0.0);
+        println!("text {:?}: width={} height={} ascent={} descent={}",
+                 item["text"].as_str().unwrap_or(""), bounds.size.width, bounds.size.height, ascent, descent);
+
+        let tolerance = item["expect_tolerance"].as_force_f32().unwrap_or(0.5);
+        for &(key, actual) in &[("expect_width", bounds.size.width), ("expect_height", bounds.size.height)] {
+            if let Some(expected) = item[key].as_force_f32() {
+                if (actual - expected).abs() > tolerance {
+                    panic!("text {:?} {} mismatch: measured {}, expected {}", item["text"], key, actual, expected);
+                }
+            }
+        }
+    }
+
+    fn handle_iframe(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml)
+    {
+        let bounds = item["bounds"].as_rect().expect("iframe must have bounds");
+        let pipeline_id = item["pipeline"].as_pipeline_id().expect("iframe must have a pipeline id");
+
+        let clip = self.to_clip_region(wrench, &item["clip"], &bounds, clip_region);
+        self.builder().push_iframe(bounds, clip, pipeline_id);
     }
 
     fn handle_text(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml)
     {
         let size = item["size"].as_pt_to_au().unwrap_or(Au::from_f32_px(16.0));
-        let color = item["color"].as_colorf().unwrap_or(*WHITE_COLOR);
+        let color = self.color_from_yaml(item).unwrap_or(*WHITE_COLOR);
         let blur_radius = item["blur_radius"].as_px_to_au().unwrap_or(Au::from_f32_px(0.0));
 
-        let (font_key, native_key) = if !item["family"].is_badvalue() {
+        let (font_key, native_key) = if !item["families"].is_badvalue() {
+            // An ordered fallback list, as in CSS. Only the first family that
+            // resolves is used; there is no per-glyph fallback yet.
+            let families = item["families"].as_vec().expect("families must be an array of names");
+            families.iter()
+                .filter_map(|family| family.as_str())
+                .filter_map(|family| wrench.try_font_key_from_name(family))
+                .next()
+                .expect(&format!("none of the font families {:?} could be resolved", item["families"]))
+        } else if !item["family"].is_badvalue() {
             wrench.font_key_from_yaml_table(item)
         } else if !item["font"].is_badvalue() {
-            let font_file = item["font"].as_str().unwrap();
-            let mut file = File::open(PathBuf::from(font_file)).expect("Couldn't open font file");
+            // font files are resolved relative to the YAML file, like images
+            let mut font_file = self.aux_dir.clone();
+            font_file.push(item["font"].as_str().unwrap());
+            let mut file = File::open(&font_file).expect("Couldn't open font file");
             let mut bytes = vec![];
             file.read_to_end(&mut bytes).expect("failed to read font file");
             wrench.font_key_from_bytes(bytes)
         } else {
             wrench.font_key_from_name(&*PLATFORM_DEFAULT_FACE_NAME)
         };
+        // palette selects a color palette for COLR fonts; other glyphs ignore it
+        let palette = item["palette"].as_i64().unwrap_or(0) as u16;
+        let font_key = wrench.font_key_with_palette(font_key, palette);
+        // subpixel_bg_color is the color subpixel AA blends the glyphs onto.
+        // It's inherited from the nearest stacking context that sets it, and
+        // otherwise is the pipeline's background_color, if it has one.
+        let pipeline_id = self.builder().pipeline_id;
+        let subpixel_bg_color = item["subpixel_bg_color"].as_colorf()
+            .or(self.subpixel_bg_colors.last().cloned())
+            .or(self.background_colors.get(&pipeline_id).cloned());
+        let font_key = wrench.font_key_with_subpixel_bg_color(font_key, subpixel_bg_color);
 
-        if item["glyphs"].is_badvalue() && item["text"].is_badvalue() {
+        // codepoints is an alternative to text for writing non-ASCII strings
+        // as an array of Unicode scalar values.
+        let text = if !item["codepoints"].is_badvalue() {
+            if !item["text"].is_badvalue() {
+                panic!("text item can't have both text and codepoints: '{:?}'", item);
+            }
+            let codepoints = item["codepoints"].as_vec_u32().expect("codepoints must be an array");
+            Some(codepoints.iter().map(|codepoint| {
+                char::from_u32(*codepoint)
+                    .expect(&format!("codepoint 0x{:x} is not a Unicode scalar value", codepoint))
+            }).collect::<String>())
+        } else {
+            item["text"].as_str().map(|text| text.to_owned())
+        };
+
+        if item["glyphs"].is_badvalue() && text.is_none() {
             panic!("text item had neither text nor glyphs!");
         }
 
-        let (glyphs, rect) = if item["text"].is_badvalue() {
+        // Vertical text runs along the y axis: the baseline is the vertical
+        // line through origin.x, and glyphs advance downwards from origin.y.
+        let vertical = item["vertical"].as_bool().unwrap_or(false);
+
+        // direction: rtl lays each line out right to left from origin, so the
+        // first character is rightmost. There's no bidi reordering; the
+        // characters are placed in the order they're written.
+        let rtl = match item["direction"].as_str() {
+            Some("ltr") | None => false,
+            Some("rtl") => true,
+            Some(direction) => panic!("direction can be ltr or rtl -- got {}", direction),
+        };
+        if rtl && vertical {
+            panic!("text item can't be both vertical and rtl: '{:?}'", item);
+        }
+
+        // origin is the position of the first glyph on the baseline. With
+        // origin_type: top_left it's the top left of the first line instead,
+        // which is moved down to the baseline by the font's ascent.
+        let baseline_offset = match item["origin_type"].as_str() {
+            Some("baseline") | None => 0.0,
+            Some("top_left") => {
+                if vertical {
+                    panic!("origin_type top_left is only supported for horizontal text");
+                }
+                let native_key = native_key.clone()
+                    .expect("origin_type top_left needs a system font to read the ascent from");
+                font_ascent(native_key, size)
+            }
+            Some(origin_type) => panic!("origin_type can be baseline or top_left -- got {}", origin_type),
+        };
+
+        let (glyphs, rect): (Vec<GlyphInstance>, LayoutRect) = if text.is_none() {
             // if glyphs are specified, then the glyph positions can have the
-            // origin baked in.
+            // origin baked in. This form skips layout entirely, so it works
+            // with raw font files as well as system fonts.
             let origin = item["origin"].as_point().unwrap_or(LayoutPoint::new(0.0, 0.0));
+            let origin = LayoutPoint::new(origin.x, origin.y + baseline_offset);
             let glyph_indices = item["glyphs"].as_vec_u32().unwrap();
             let glyph_offsets = item["offsets"].as_vec_f32().unwrap();
-            assert!(glyph_offsets.len() == glyph_indices.len() * 2);
 
-            let glyphs = glyph_indices.iter().enumerate().map(|k| {
-                GlyphInstance {
-                    index: *k.1,
-                    x: origin.x + glyph_offsets[k.0*2],
-                    y: origin.y + glyph_offsets[k.0*2+1],
-                }
-            }).collect();
+            // Offsets are normally x,y pairs. Vertical text may instead give a
+            // single offset per glyph, measured along the vertical baseline.
+            let glyphs = if vertical && glyph_offsets.len() == glyph_indices.len() {
+                glyph_indices.iter().enumerate().map(|k| {
+                    GlyphInstance {
+                        index: *k.1,
+                        x: origin.x,
+                        y: origin.y + glyph_offsets[k.0],
+                    }
+                }).collect()
+            } else {
+                assert!(glyph_offsets.len() == glyph_indices.len() * 2);
+                glyph_indices.iter().enumerate().map(|k| {
+                    GlyphInstance {
+                        index: *k.1,
+                        x: origin.x + glyph_offsets[k.0*2],
+                        y: origin.y + glyph_offsets[k.0*2+1],
+                    }
+                }).collect()
+            };
             // TODO(gw): We could optionally use the WR API to query glyph dimensions
             //           here and calculate the bounding region here if we want to.
             let rect = item["bounds"].as_rect()
@@ -154,30 +1560,146 @@ impl YamlFrameReader {
             (glyphs, rect)
         } else {
             if native_key.is_none() {
-                panic!("Can't layout simple ascii text with raw font [for now]");
+                panic!("Can't layout simple ascii text with a raw font file [for now]; \
+                        use the pre-laid-out form with 'glyphs' and 'offsets' instead");
             }
             let native_key = native_key.unwrap();
-            let text = item["text"].as_str().unwrap();
-            let (glyph_indices, glyph_advances) =
-                layout_simple_ascii(native_key, text, size);
+            let text = text.unwrap();
             let origin = item["origin"].as_point()
                 .expect("origin required for text without glyphs");
+            let origin = LayoutPoint::new(origin.x, origin.y + baseline_offset);
+
+            // letter_spacing is added to every glyph advance. Each '\n' starts
+            // a new line line_height further down (or, for vertical text, a
+            // new column line_height to the left). We don't have font metrics
+            // here, so the natural line height is taken to be the font size.
+            let letter_spacing = item["letter_spacing"].as_force_f32().unwrap_or(0.0);
+            let line_height = item["line_height"].as_force_f32().unwrap_or(size.to_f32_px());
+
+            // kerning maps a pair of adjacent characters, such as "AV", to an
+            // adjustment added to the advance of the first of them.
+            let mut kerning = HashMap::new();
+            if let Some(pairs) = item["kerning"].as_hash() {
+                for (pair, adjustment) in pairs {
+                    let pair_str = pair.as_str().expect("kerning pairs must be strings");
+                    let chars: Vec<char> = pair_str.chars().collect();
+                    if chars.len() != 2 {
+                        panic!("kerning pair must be two characters, got '{}'", pair_str);
+                    }
+                    let adjustment = adjustment.as_force_f32()
+                        .expect(&format!("kerning adjustment for '{}' must be a number", pair_str));
+                    kerning.insert((chars[0], chars[1]), adjustment);
+                }
+            }
+
+            // A character the font lacks is laid out as glyph 0, the font's
+            // notdef glyph. fallback_glyph draws a different glyph index in
+            // its place, and fallback_char the glyph, with its advance, of a
+            // character the font has.
+            let fallback = match (item["fallback_glyph"].as_i64(), item["fallback_char"].as_str()) {
+                (Some(_), Some(_)) => panic!("text item can't have both fallback_glyph and fallback_char: '{:?}'", item),
+                (Some(glyph), None) => Some((glyph as u16, None)),
+                (None, Some(fallback_char)) => {
+                    if fallback_char.chars().count() != 1 {
+                        panic!("fallback_char must be a single character, got '{}'", fallback_char);
+                    }
+                    let (indices, advances) = layout_simple_ascii(native_key.clone(), fallback_char, size);
+                    Some((indices[0], Some(advances[0])))
+                }
+                (None, None) => None,
+            };
+
+            let mut glyphs = Vec::new();
+            for (line_index, line) in text.split('\n').enumerate() {
+                let line_offset = line_index as f32 * line_height;
+                let (mut x, mut y) = if vertical {
+                    (origin.x - line_offset, origin.y)
+                } else {
+                    (origin.x, origin.y + line_offset)
+                };
 
-            let mut x = origin.x;
-            let y = origin.y;
-            let glyphs = glyph_indices.iter().zip(glyph_advances).map(|arg| {
-                let gi = GlyphInstance { index: *arg.0 as u32, x: x, y: y };
-                x = x + arg.1;
-                gi
-            }).collect();
-            let rect = LayoutRect::new(LayoutPoint::new(0.0, 0.0), wrench.window_size_f32());
+                let (mut glyph_indices, mut glyph_advances) =
+                    layout_simple_ascii(native_key.clone(), line, size);
+                if let Some((fallback_index, fallback_advance)) = fallback {
+                    for (index, advance) in glyph_indices.iter_mut().zip(glyph_advances.iter_mut()) {
+                        if *index == 0 {
+                            *index = fallback_index;
+                            *advance = fallback_advance.unwrap_or(*advance);
+                        }
+                    }
+                }
+                let chars: Vec<char> = line.chars().collect();
+                for (i, pair) in chars.windows(2).enumerate() {
+                    if let Some(adjustment) = kerning.get(&(pair[0], pair[1])) {
+                        glyph_advances[i] += *adjustment;
+                    }
+                }
+                for (index, advance) in glyph_indices.iter().zip(glyph_advances) {
+                    if rtl {
+                        x = x - advance - letter_spacing;
+                        glyphs.push(GlyphInstance { index: *index as u32, x: x, y: y });
+                        continue;
+                    }
+                    glyphs.push(GlyphInstance { index: *index as u32, x: x, y: y });
+                    if vertical {
+                        y = y + advance + letter_spacing;
+                    } else {
+                        x = x + advance + letter_spacing;
+                    }
+                }
+            }
+            // Rather than assuming the text is inside the window, cover the
+            // glyph origins with a margin of one font size on every side, so
+            // text at negative or off-screen positions keeps its geometry.
+            let rect = item["bounds"].as_rect().unwrap_or_else(|| {
+                glyph_bounds(&glyphs).inflate(size.to_f32_px(), size.to_f32_px())
+            });
             (glyphs, rect)
         };
 
-        let builder = self.builder();
-        let clip = item["clip"].as_clip_region(builder).unwrap_or(*clip_region);
+        // Snap glyph origins to the nearest whole pixel, with halves rounding
+        // away from zero, so snapped and subpixel text can be compared.
+        let glyphs = if self.subpixel_positioning {
+            glyphs
+        } else {
+            glyphs.into_iter().map(|glyph| {
+                GlyphInstance { index: glyph.index, x: glyph.x.round(), y: glyph.y.round() }
+            }).collect()
+        };
+
+        if self.measure_text {
+            self.measure_text_item(wrench, item, font_key, size, &glyphs);
+            return;
+        }
+
+        let clip = self.to_clip_region(wrench, &item["clip"], &rect, clip_region);
+
+        // glyph_transforms gives a transform for each of the glyphs, applied
+        // about the glyph's origin. Each glyph is then pushed as a text item
+        // of its own in a stacking context of its own, which costs a stacking
+        // context and a text item per glyph.
+        if let Some(transforms) = item["glyph_transforms"].as_vec() {
+            if item["glyphs"].is_badvalue() {
+                panic!("glyph_transforms requires glyphs: '{:?}'", item);
+            }
+            if transforms.len() != glyphs.len() {
+                panic!("text item has {} glyph_transforms for {} glyphs", transforms.len(), glyphs.len());
+            }
+            for (glyph, transform) in glyphs.into_iter().zip(transforms) {
+                let transform = transform.as_matrix4d()
+                    .expect(&format!("invalid glyph transform '{:?}'", transform));
+                let transform = LayoutTransform::create_translation(glyph.x, glyph.y, 0.0)
+                    .pre_mul(&transform)
+                    .pre_translated(-glyph.x, -glyph.y, 0.0);
+                self.push_item_stacking_context(wrench, 0, &transform);
+                self.builder().push_text(rect, clip, vec![glyph], font_key, color, size, blur_radius);
+                self.builder().pop_stacking_context();
+            }
+            return;
+        }
+
         // FIXME this is the full bounds of the glyphs; we should calculate this more accurately
-        builder.push_text(rect, clip, glyphs, font_key, color, size, blur_radius);
+        self.builder().push_text(rect, clip, glyphs, font_key, color, size, blur_radius);
     }
 
     pub fn add_display_list_items_from_yaml(&mut self, wrench: &mut Wrench, yaml: &Yaml) {
@@ -187,56 +1709,257 @@ impl YamlFrameReader {
                                            Vec::new(), None)
         };
 
-        for ref item in yaml.as_vec().unwrap() {
-            // handle shorthand first
-            if !item["rect"].is_badvalue() {
-                self.handle_rect(wrench, &full_clip_region, &item);
-                continue;
+        for item in paint_order(yaml.as_vec().unwrap()) {
+            // items for another pipeline are built with that pipeline's root
+            if let Some(target) = item["in_pipeline"].as_pipeline_id() {
+                if target != self.builder().pipeline_id {
+                    continue;
+                }
             }
 
-            if !item["image"].is_badvalue() {
-                self.handle_image(wrench, &full_clip_region, &item);
+            if !item["repeat"].is_badvalue() {
+                self.add_repeated_item_from_yaml(wrench, &full_clip_region, &item);
                 continue;
             }
 
-            if !item["text"].is_badvalue() || !item["glyphs"].is_badvalue() {
-                self.handle_text(wrench, &full_clip_region, &item);
-                continue;
+            self.add_clipped_item_from_yaml(wrench, &full_clip_region, &item);
+        }
+    }
+
+    fn add_clipped_item_from_yaml(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml) {
+        // With clip_space: parent, the clip rect is given in the space the
+        // enclosing stacking context is placed in, before its bounds offset
+        // and transform. It's mapped into the stacking context by the inverse
+        // of translate(bounds.origin) * transform; perspective is ignored.
+        let local_item;
+        let item = match item["clip_space"].as_str() {
+            Some("local") | None => item,
+            Some("parent") => {
+                let to_local = self.stacking_context_transforms.last()
+                    .expect("clip_space: parent used outside of a stacking context")
+                    .inverse()
+                    .expect("clip_space: parent needs a stacking context transform that can be inverted");
+                local_item = transform_item_clip(item, &to_local);
+                &local_item
             }
+            Some(space) => panic!("clip_space can be local or parent -- got {}", space),
+        };
 
-            if !item["stacking_context"].is_badvalue() {
-                self.add_stacking_context_from_yaml(wrench, &item);
-                continue;
+        // A z_index on a leaf item wraps it in its own stacking context at
+        // that z_index, which costs one extra stacking context per item.
+        let is_stacking_context = !item["stacking_context"].is_badvalue() ||
+                                  item["type"].as_str() == Some("stacking_context");
+        let z_index = if is_stacking_context || item["z_index"].is_badvalue() {
+            None
+        } else {
+            Some(item["z_index"].as_i64().expect(&format!("z_index must be an integer, got {:?}", item["z_index"])))
+        };
+        if let Some(z_index) = z_index {
+            self.push_item_stacking_context(wrench, z_index as i32, &LayoutTransform::identity());
+        }
+
+        let first_new_item = self.builder().list.len();
+
+        if !item["clip"]["exclude"].is_badvalue() {
+            self.add_excluded_clip_item_from_yaml(wrench, clip_region, item);
+        } else {
+            self.add_item_from_yaml(wrench, clip_region, item);
+        }
+
+        // A stacking context's children outline themselves as they're added.
+        if let Some(outline_color) = self.debug_outline_color {
+            if !is_stacking_context {
+                self.add_debug_outlines(first_new_item, outline_color);
             }
+        }
+
+        if z_index.is_some() {
+            self.builder().pop_stacking_context();
+        }
+    }
+
+    /// Pushes a stacking context wrapping a single item. It covers the
+    /// enclosing stacking context at the same origin, so the item's
+    /// coordinates are unchanged apart from the transform.
+    fn push_item_stacking_context(&mut self, wrench: &mut Wrench, z_index: i32, transform: &LayoutTransform) {
+        let bounds = self.stacking_context_bounds.last().cloned().unwrap_or_else(|| {
+            LayoutRect::new(LayoutPoint::new(0.0, 0.0), wrench.window_size_f32())
+        });
+        let builder = self.builder();
+        let clip = builder.new_clip_region(&bounds, Vec::new(), None);
+        builder.push_stacking_context(ScrollPolicy::Scrollable,
+                                      bounds,
+                                      clip,
+                                      z_index,
+                                      transform,
+                                      &LayoutTransform::identity(),
+                                      MixBlendMode::Normal,
+                                      Vec::new());
+    }
 
-            // handle 'type: xxx' longhand
-            match item["type"].as_str() {
-                Some("rect") => self.handle_rect(wrench, &full_clip_region, &item),
-                Some("image") => self.handle_image(wrench, &full_clip_region, &item),
-                Some("text") => self.handle_text(wrench, &full_clip_region, &item),
-                Some("stacking_context") => self.add_stacking_context_from_yaml(wrench, &item),
+    /// Outlines the bounds of each item pushed since `first_item` with a
+    /// one pixel border, including items inside the stacking contexts the
+    /// reader wraps them in. Each outline goes right after its item, so it's
+    /// in the same stacking context and coordinate space.
+    fn add_debug_outlines(&mut self, first_item: usize, color: ColorF) {
+        let side = BorderSide {
+            width: 1.0,
+            color: color,
+            style: BorderStyle::Solid,
+        };
+        let items = self.builder().list.split_off(first_item);
+        for item in items {
+            self.builder().list.push(item);
+            match item.item {
+                SpecificDisplayItem::PushStackingContext(..) |
+                SpecificDisplayItem::PushScrollLayer(..) |
+                SpecificDisplayItem::PopStackingContext |
+                SpecificDisplayItem::PopScrollLayer => {}
                 _ => {
-                    //println!("Skipping {:?}", item);
+                    let clip = ClipRegion::simple(&item.rect);
+                    self.builder().push_border(item.rect, clip, side, side, side, side, BorderRadius::zero());
                 }
             }
         }
     }
 
+    /// Handles `repeat: { count: N, offset: [dx, dy] }`, which pushes N copies
+    /// of an item, each translated by a further `offset` from the previous.
+    fn add_repeated_item_from_yaml(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml) {
+        let count = item["repeat"]["count"].as_i64().expect("repeat requires an integer count");
+        if count <= 0 || count > MAX_REPEAT_COUNT {
+            panic!("repeat count must be between 1 and {}, got {}", MAX_REPEAT_COUNT, count);
+        }
+        let offset = item["repeat"]["offset"].as_point().unwrap_or(LayoutPoint::new(0.0, 0.0));
+
+        for i in 0..count {
+            let copy = translate_item(item, LayoutPoint::new(offset.x * i as f32, offset.y * i as f32));
+            self.add_clipped_item_from_yaml(wrench, clip_region, &copy);
+        }
+    }
+
+    fn add_item_from_yaml(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml) {
+        // handle shorthand first
+        if !item["rect"].is_badvalue() {
+            self.handle_rect(wrench, clip_region, item);
+            return;
+        }
+
+        if !item["image"].is_badvalue() {
+            self.handle_image(wrench, clip_region, item);
+            return;
+        }
+
+        if !item["text"].is_badvalue() || !item["glyphs"].is_badvalue() {
+            self.handle_text(wrench, clip_region, item);
+            return;
+        }
+
+        if !item["stacking_context"].is_badvalue() {
+            self.add_stacking_context_from_yaml(wrench, item);
+            return;
+        }
+
+        // handle 'type: xxx' longhand
+        match item["type"].as_str() {
+            Some("rect") => self.handle_rect(wrench, clip_region, item),
+            Some("image") => self.handle_image(wrench, clip_region, item),
+            Some("text") => self.handle_text(wrench, clip_region, item),
+            Some("iframe") => self.handle_iframe(wrench, clip_region, item),
+            Some("checkerboard") => self.handle_checkerboard(wrench, clip_region, item),
+            Some("border_image") => self.handle_border_image(wrench, clip_region, item),
+            Some("background_fill") => self.handle_background_fill(item),
+            Some("stacking_context") => self.add_stacking_context_from_yaml(wrench, item),
+            _ => {
+                //println!("Skipping {:?}", item);
+            }
+        }
+    }
+
+    /// Handles `clip: { exclude: rect }`, which draws everything in the item's
+    /// bounds except the excluded rect. WebRender has no inverse clips, so the
+    /// item is pushed up to four times, each clipped to one of the bands of
+    /// the bounds that surround the excluded rect.
+    fn add_excluded_clip_item_from_yaml(&mut self, wrench: &mut Wrench, clip_region: &ClipRegion, item: &Yaml) {
+        if !item["stacking_context"].is_badvalue() || item["type"].as_str() == Some("stacking_context") {
+            panic!("clip exclude is only supported on leaf items: '{:?}'", item);
+        }
+
+        let bounds = item[if item["rect"].is_badvalue() { "bounds" } else { "rect" }].as_rect()
+            .expect("clip exclude requires the item to have a full bounds rect");
+        let exclude = item["clip"]["exclude"].as_rect().unwrap();
+        if bounds.union(&exclude) != bounds {
+            panic!("clip exclude rect {:?} must lie within the item bounds {:?}", exclude, bounds);
+        }
+
+        let bands = [
+            LayoutRect::new(bounds.origin,
+                            LayoutSize::new(bounds.size.width, exclude.origin.y - bounds.origin.y)),
+            LayoutRect::new(LayoutPoint::new(bounds.origin.x, exclude.max_y()),
+                            LayoutSize::new(bounds.size.width, bounds.max_y() - exclude.max_y())),
+            LayoutRect::new(LayoutPoint::new(bounds.origin.x, exclude.origin.y),
+                            LayoutSize::new(exclude.origin.x - bounds.origin.x, exclude.size.height)),
+            LayoutRect::new(LayoutPoint::new(exclude.max_x(), exclude.origin.y),
+                            LayoutSize::new(bounds.max_x() - exclude.max_x(), exclude.size.height)),
+        ];
+
+        for band in bands.iter().filter(|band| !band.is_empty()) {
+            let band = band.intersection(&clip_region.main).unwrap_or(LayoutRect::zero());
+            let band_clip = self.builder().new_clip_region(&band, Vec::new(), None);
+            self.add_item_from_yaml(wrench, &band_clip, item);
+        }
+    }
+
     pub fn add_stacking_context_from_yaml(&mut self, wrench: &mut Wrench, yaml: &Yaml) {
+        if !yaml["establishes_context"].as_bool().unwrap_or(true) {
+            self.add_containing_block_from_yaml(wrench, yaml);
+            return;
+        }
+
         let bounds = yaml["bounds"].as_rect().unwrap_or(LayoutRect::new(LayoutPoint::new(0.0, 0.0), wrench.window_size_f32()));
         let overflow_bounds = yaml["overflow"].as_rect().unwrap_or(bounds);
         let z_index = yaml["z_index"].as_i64().unwrap_or(0);
-        let transform = yaml["transform"].as_matrix4d().unwrap_or(LayoutTransform::identity());
+        let scroll_policy = match yaml["scroll_policy"].as_str() {
+            Some("scrollable") | None => ScrollPolicy::Scrollable,
+            Some("fixed") => ScrollPolicy::Fixed,
+            Some(_) => panic!("scroll_policy can be scrollable or fixed -- got {:?}", yaml),
+        };
+        let mut transform = yaml["transform"].as_matrix4d().unwrap_or(LayoutTransform::identity());
+
+        // A scroll offset is applied by translating the content. Fixed
+        // content doesn't scroll, so it ignores the offset.
+        if let Some(scroll_offset) = yaml["scroll_offset"].as_point() {
+            if scroll_policy == ScrollPolicy::Scrollable {
+                transform = transform.pre_translated(-scroll_offset.x, -scroll_offset.y, 0.0);
+            }
+        }
+        // The outermost stacking context being built is its pipeline's root.
+        let is_pipeline_root = self.stacking_context_bounds.is_empty();
+        // Only set while building a pipeline's root stacking context.
+        if let Some(root_transform) = self.root_transform.take() {
+            transform = root_transform.pre_mul(&transform);
+        }
         let perspective = yaml["perspective"].as_matrix4d().unwrap_or(LayoutTransform::identity());
 
-        // FIXME handle these
+        // cache: true is an advisory hint that the stacking context's contents
+        // can be cached as a surface. WebRender doesn't cache stacking
+        // contexts or take hints yet, so it's only checked here.
+        if !yaml["cache"].is_badvalue() && yaml["cache"].as_bool().is_none() {
+            panic!("stacking context cache must be true or false, got {:?}", yaml["cache"]);
+        }
+
+        // FIXME handle this
         let mix_blend_mode = MixBlendMode::Normal;
-        let filters: Vec<FilterOp> = Vec::new();
+        let filters = yaml["filters"].as_vec_filter_op().unwrap_or(Vec::new());
+        if filters.len() > MAX_FILTER_COUNT {
+            panic!("stacking context has {} filters, more than the maximum of {}",
+                   filters.len(), MAX_FILTER_COUNT);
+        }
 
         {
             let builder = self.builder();
             let clip = builder.new_clip_region(&overflow_bounds, vec![], None);
-            builder.push_stacking_context(ScrollPolicy::Scrollable,
+            builder.push_stacking_context(scroll_policy,
                                           bounds,
                                           clip,
                                           z_index as i32,
@@ -246,26 +1969,95 @@ impl YamlFrameReader {
                                           filters);
         }
 
+        // Only set while building the root stacking context.
+        if let Some(file) = self.root_background_image.take() {
+            let (image_key, _) = wrench.add_or_get_image(&file, ImageOptions::default());
+            let background_rect = LayoutRect::new(LayoutPoint::new(0.0, 0.0), bounds.size);
+            let builder = self.builder();
+            let clip = builder.new_clip_region(&background_rect, Vec::new(), None);
+            builder.push_image(background_rect,
+                               clip,
+                               background_rect.size,
+                               LayoutSize::new(0.0, 0.0),
+                               ImageRendering::Auto,
+                               image_key);
+        }
+
+        // The overflow rect is in the parent's space, but the root's items
+        // are in its local space, which starts at its bounds' origin.
+        if is_pipeline_root {
+            self.pipeline_overflow = Some(overflow_bounds.translate(&-bounds.origin));
+        }
+        let subpixel_bg_color = yaml["subpixel_bg_color"].as_colorf();
+        if let Some(color) = subpixel_bg_color {
+            self.subpixel_bg_colors.push(color);
+        }
+        self.stacking_context_bounds.push(LayoutRect::new(LayoutPoint::new(0.0, 0.0), bounds.size));
+        self.stacking_context_transforms.push(
+            LayoutTransform::create_translation(bounds.origin.x, bounds.origin.y, 0.0).pre_mul(&transform));
         if !yaml["items"].is_badvalue() {
             self.add_display_list_items_from_yaml(wrench, &yaml["items"]);
         }
+        if is_pipeline_root {
+            let pipeline_id = self.builder().pipeline_id;
+            if let Some(moved_items) = self.moved_items.remove(&pipeline_id) {
+                self.add_display_list_items_from_yaml(wrench, &Yaml::Array(moved_items));
+            }
+        }
+        self.stacking_context_bounds.pop();
+        self.stacking_context_transforms.pop();
+        if subpixel_bg_color.is_some() {
+            self.subpixel_bg_colors.pop();
+        }
 
         self.builder().pop_stacking_context();
     }
+
+    /// Handles a stacking context with `establishes_context: false`, which
+    /// is only a containing block, as in CSS: its items are offset by its
+    /// bounds but are painted as part of the enclosing stacking context, so
+    /// they interleave with the items around it. Everything that would need
+    /// a stacking context of its own is rejected.
+    fn add_containing_block_from_yaml(&mut self, wrench: &mut Wrench, yaml: &Yaml) {
+        if self.stacking_context_bounds.is_empty() {
+            panic!("a pipeline's root always establishes a stacking context");
+        }
+        for key in &["z_index", "transform", "perspective", "filters", "scroll_offset", "scroll_policy"] {
+            if !yaml[*key].is_badvalue() {
+                panic!("stacking context with establishes_context: false can't have a {}", key);
+            }
+        }
+
+        let offset = yaml["bounds"].as_rect().map_or(LayoutPoint::new(0.0, 0.0), |bounds| bounds.origin);
+        if let Some(items) = yaml["items"].as_vec() {
+            let items = items.iter().map(|item| translate_item(item, offset)).collect();
+            self.add_display_list_items_from_yaml(wrench, &Yaml::Array(items));
+        }
+    }
+
+    fn set_sequence_index(&mut self, index: usize) {
+        if index != self.sequence_index {
+            self.sequence_index = index;
+            self.frame_built = false;
+        }
+    }
 }
 
 impl WrenchThing for YamlFrameReader {
     fn do_frame(&mut self, wrench: &mut Wrench) -> u32 {
         if !self.frame_built {
-            self.builder = Some(DisplayListBuilder::new(wrench.root_pipeline_id));
+            self.rebuild(wrench);
 
-            self.build(wrench);
+            // only the first document is benchmarked
+            if let (Some(iterations), 0) = (self.bench_iterations, self.frame_count) {
+                self.run_benchmark(wrench, iterations);
+            }
         }
 
         self.frame_count += 1;
 
         if !self.frame_built || wrench.should_rebuild_display_lists() {
-            wrench.send_lists(self.frame_count, self.builder.as_ref().unwrap().clone());
+            self.submit(wrench);
         } else {
             wrench.refresh();
         }
@@ -275,12 +2067,36 @@ impl WrenchThing for YamlFrameReader {
     }
 
     fn next_frame(&mut self) {
+        let index = if self.sequence_index + 1 < self.sequence_length {
+            self.sequence_index + 1
+        } else if self.loop_sequence {
+            0
+        } else {
+            self.sequence_index
+        };
+        self.set_sequence_index(index);
     }
 
     fn prev_frame(&mut self) {
+        let index = if self.sequence_index > 0 {
+            self.sequence_index - 1
+        } else if self.loop_sequence {
+            self.sequence_length.saturating_sub(1)
+        } else {
+            self.sequence_index
+        };
+        self.set_sequence_index(index);
     }
 
     fn queue_frames(&self) -> u32 {
         self.queue_depth
     }
+
+    fn is_last_frame(&self) -> bool {
+        self.sequence_index + 1 >= self.sequence_length
+    }
+
+    fn is_capture_frame(&self) -> bool {
+        self.sequence_index == self.capture_frame
+    }
 }
@@ -183,12 +183,18 @@ struct CachedImage {
 
 pub struct YamlFrameWriter {
     frame_base: PathBuf,
+    /// If set, every frame is written to this file rather than to a
+    /// numbered file in `frame_base`.
+    frame_file: Option<PathBuf>,
     rsrc_base: PathBuf,
     next_rsrc_num: u32,
     images: HashMap<ImageKey, CachedImage>,
     fonts: HashMap<FontKey, CachedFont>,
 
     last_frame_written: u32,
+    /// The pipeline whose display lists are written. Until the root
+    /// pipeline is set, whichever is submitted first in a frame is.
+    root_pipeline_id: Option<PipelineId>,
 
     dl_descriptor: Option<BuiltDisplayListDescriptor>,
     aux_descriptor: Option<AuxiliaryListsDescriptor>,
@@ -202,6 +208,7 @@ impl YamlFrameWriter {
 
         YamlFrameWriter {
             frame_base: path.to_owned(),
+            frame_file: None,
             rsrc_base: rsrc_base,
             next_rsrc_num: 1,
             images: HashMap::new(),
@@ -211,17 +218,32 @@ impl YamlFrameWriter {
             aux_descriptor: None,
 
             last_frame_written: u32::max_value(),
+            root_pipeline_id: None,
         }
     }
 
+    /// Creates a writer that writes the root display list of each frame to
+    /// `file`, overwriting the previous frame, with resources alongside it.
+    pub fn new_for_file(file: &Path) -> YamlFrameWriter {
+        let base = file.parent().map_or(PathBuf::from("."), |parent| parent.to_owned());
+        let mut writer = YamlFrameWriter::new(&base);
+        writer.frame_file = Some(file.to_owned());
+        writer
+    }
+
     pub fn begin_write_root_display_list(&mut self,
                                          _: &Option<ColorF>,
                                          _: &Epoch,
-                                         _: &PipelineId,
+                                         pipeline_id: &PipelineId,
                                          _: &LayoutSize,
                                          display_list: &BuiltDisplayListDescriptor,
                                          auxiliary_lists: &AuxiliaryListsDescriptor)
     {
+        // Iframes' pipelines are submitted in the same frame as the root's.
+        if self.root_pipeline_id.map_or(false, |root_pipeline_id| root_pipeline_id != *pipeline_id) {
+            return;
+        }
+
         unsafe {
             if CURRENT_FRAME_NUMBER == self.last_frame_written {
                 return;
@@ -266,9 +288,15 @@ impl YamlFrameWriter {
             emitter.dump(&Yaml::Hash(root)).unwrap();
         }
         let sb = s.into_bytes();
-        let mut frame_file_name = self.frame_base.clone();
-        let current_shown_frame = unsafe { CURRENT_FRAME_NUMBER };
-        frame_file_name.push(format!("frame-{}.yaml", current_shown_frame));
+        let frame_file_name = match self.frame_file {
+            Some(ref file) => file.clone(),
+            None => {
+                let mut frame_file_name = self.frame_base.clone();
+                let current_shown_frame = unsafe { CURRENT_FRAME_NUMBER };
+                frame_file_name.push(format!("frame-{}.yaml", current_shown_frame));
+                frame_file_name
+            }
+        };
         let mut file = File::create(&frame_file_name).unwrap();
         file.write_all(&sb).unwrap();
     }
@@ -486,7 +514,10 @@ impl YamlFrameWriter {
 impl webrender::ApiRecordingReceiver for YamlFrameWriter {
     fn write_msg(&mut self, _: u32, msg: &ApiMsg) {
         match msg {
-            &ApiMsg::SetRootPipeline(..) |
+            &ApiMsg::SetRootPipeline(ref pipeline_id) => {
+                self.root_pipeline_id = Some(*pipeline_id);
+            }
+
             &ApiMsg::Scroll(..) |
             &ApiMsg::TickScrollingBounce |
             &ApiMsg::WebGLCommand(..) => {
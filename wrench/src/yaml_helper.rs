@@ -2,6 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use std::f32::consts::PI;
 use std::str::FromStr;
 use app_units::Au;
 
@@ -9,17 +10,111 @@ use yaml_rust::Yaml;
 
 use webrender_traits::*;
 
+fn parse_css_angle(token: &str) -> f32 {
+    let (value, scale) = if token.ends_with("deg") {
+        (&token[..token.len() - 3], PI / 180.0)
+    } else if token.ends_with("grad") {
+        (&token[..token.len() - 4], PI / 200.0)
+    } else if token.ends_with("rad") {
+        (&token[..token.len() - 3], 1.0)
+    } else if token.ends_with("turn") {
+        (&token[..token.len() - 4], 2.0 * PI)
+    } else {
+        panic!("expected an angle in deg, grad, rad or turn, got '{}'", token);
+    };
+    f32::from_str(value).expect(&format!("invalid angle '{}'", token)) * scale
+}
+
+fn parse_css_number(token: &str) -> f32 {
+    let value = if token.ends_with("px") { &token[..token.len() - 2] } else { token };
+    f32::from_str(value).expect(&format!("invalid transform value '{}'", token))
+}
+
+/// Parses a CSS transform list such as `rotate(45deg) translate(10px, 20px)`.
+/// As in CSS, the functions are applied to content from right to left.
+pub fn parse_css_transform(css: &str) -> LayoutTransform {
+    let mut transform = LayoutTransform::identity();
+    let mut rest = css.trim();
+
+    while !rest.is_empty() {
+        let open = rest.find('(').expect(&format!("expected a transform function at '{}'", rest));
+        let close = rest.find(')').expect(&format!("unterminated transform function at '{}'", rest));
+        let function = rest[..open].trim();
+        let args: Vec<&str> = rest[open + 1..close].split(|c: char| c == ',' || c.is_whitespace())
+                                                  .filter(|arg| !arg.is_empty())
+                                                  .collect();
+        let token = &rest[..close + 1];
+        rest = rest[close + 1..].trim();
+
+        let rotation = |angle: f32| (angle.cos(), angle.sin());
+        let op = match (function, args.len()) {
+            ("translate", 1) => LayoutTransform::create_translation(parse_css_number(args[0]), 0.0, 0.0),
+            ("translate", 2) => LayoutTransform::create_translation(parse_css_number(args[0]),
+                                                                    parse_css_number(args[1]),
+                                                                    0.0),
+            ("translateX", 1) => LayoutTransform::create_translation(parse_css_number(args[0]), 0.0, 0.0),
+            ("translateY", 1) => LayoutTransform::create_translation(0.0, parse_css_number(args[0]), 0.0),
+            ("scale", 1) => {
+                let scale = parse_css_number(args[0]);
+                LayoutTransform::create_scale(scale, scale, 1.0)
+            }
+            ("scale", 2) => LayoutTransform::create_scale(parse_css_number(args[0]),
+                                                          parse_css_number(args[1]),
+                                                          1.0),
+            ("scaleX", 1) => LayoutTransform::create_scale(parse_css_number(args[0]), 1.0, 1.0),
+            ("scaleY", 1) => LayoutTransform::create_scale(1.0, parse_css_number(args[0]), 1.0),
+            ("rotate", 1) | ("rotateZ", 1) => {
+                let (cos, sin) = rotation(parse_css_angle(args[0]));
+                LayoutTransform::row_major(cos, sin, 0.0, 0.0,
+                                           -sin, cos, 0.0, 0.0,
+                                           0.0, 0.0, 1.0, 0.0,
+                                           0.0, 0.0, 0.0, 1.0)
+            }
+            ("rotateX", 1) => {
+                let (cos, sin) = rotation(parse_css_angle(args[0]));
+                LayoutTransform::row_major(1.0, 0.0, 0.0, 0.0,
+                                           0.0, cos, sin, 0.0,
+                                           0.0, -sin, cos, 0.0,
+                                           0.0, 0.0, 0.0, 1.0)
+            }
+            ("rotateY", 1) => {
+                let (cos, sin) = rotation(parse_css_angle(args[0]));
+                LayoutTransform::row_major(cos, 0.0, -sin, 0.0,
+                                           0.0, 1.0, 0.0, 0.0,
+                                           sin, 0.0, cos, 0.0,
+                                           0.0, 0.0, 0.0, 1.0)
+            }
+            ("matrix", 6) => {
+                let m: Vec<f32> = args.iter().map(|arg| parse_css_number(arg)).collect();
+                LayoutTransform::row_major(m[0], m[1], 0.0, 0.0,
+                                           m[2], m[3], 0.0, 0.0,
+                                           0.0, 0.0, 1.0, 0.0,
+                                           m[4], m[5], 0.0, 1.0)
+            }
+            _ => panic!("unsupported transform function '{}'", token),
+        };
+        transform = transform.pre_mul(&op);
+    }
+
+    transform
+}
+
 pub trait YamlHelper {
     fn as_force_f32(&self) -> Option<f32>;
     fn as_vec_f32(&self) -> Option<Vec<f32>>;
     fn as_vec_u32(&self) -> Option<Vec<u32>>;
+    fn as_pipeline_id(&self) -> Option<PipelineId>;
     fn as_rect(&self) -> Option<LayoutRect>;
     fn as_size(&self) -> Option<LayoutSize>;
     fn as_point(&self) -> Option<LayoutPoint>;
     fn as_matrix4d(&self) -> Option<LayoutTransform>;
     fn as_colorf(&self) -> Option<ColorF>;
     fn as_complex_clip_rect(&self) -> Option<ComplexClipRegion>;
+    fn as_border_radius(&self) -> Option<BorderRadius>;
     fn as_clip_region(&self, &mut DisplayListBuilder) -> Option<ClipRegion>;
+    fn as_gradient_stops(&self) -> Option<Vec<GradientStop>>;
+    fn as_filter_op(&self) -> Option<FilterOp>;
+    fn as_vec_filter_op(&self) -> Option<Vec<FilterOp>>;
     fn as_px_to_au(&self) -> Option<Au>;
     fn as_pt_to_au(&self) -> Option<Au>;
 }
@@ -28,7 +123,7 @@ impl YamlHelper for Yaml {
     fn as_force_f32(&self) -> Option<f32> {
         match *self {
             Yaml::Integer(iv) => Some(iv as f32),
-            Yaml::String(ref sv) => match f32::from_str(sv.as_str()) {
+            Yaml::String(ref sv) | Yaml::Real(ref sv) => match f32::from_str(sv.as_str()) {
                 Ok(v) => Some(v),
                 Err(_) => None
             },
@@ -62,6 +157,36 @@ impl YamlHelper for Yaml {
         }
     }
 
+    fn as_gradient_stops(&self) -> Option<Vec<GradientStop>> {
+        if self.is_badvalue() {
+            return None;
+        }
+
+        // stops are a flat list of offset, color pairs
+        let values = self.as_vec().expect("gradient stops must be an array of offsets and colors");
+        if values.len() % 2 != 0 {
+            panic!("gradient stops expected offset, color pairs; got '{:?}'", self);
+        }
+        Some(values.chunks(2).map(|stop| {
+            GradientStop {
+                offset: stop[0].as_force_f32().expect(&format!("expected gradient stop offset, got '{:?}'", stop[0])),
+                color: stop[1].as_colorf().expect(&format!("expected gradient stop color, got '{:?}'", stop[1])),
+            }
+        }).collect())
+    }
+
+    fn as_pipeline_id(&self) -> Option<PipelineId> {
+        if self.is_badvalue() {
+            return None;
+        }
+
+        let nums = self.as_vec_u32().expect(&format!("pipeline id expected 2 integers, got '{:?}'", self));
+        if nums.len() != 2 {
+            panic!("pipeline id expected 2 integers, got {} instead ('{:?}')", nums.len(), self);
+        }
+        Some(PipelineId(nums[0], nums[1]))
+    }
+
     fn as_px_to_au(&self) -> Option<Au> {
         match self.as_force_f32() {
             Some(fv) => Some(Au::from_f32_px(fv)),
@@ -113,7 +238,24 @@ impl YamlHelper for Yaml {
     }
 
     fn as_matrix4d(&self) -> Option<LayoutTransform> {
-        None
+        if self.is_badvalue() {
+            return None;
+        }
+
+        if let Some(s) = self.as_str() {
+            if s.contains('(') {
+                return Some(parse_css_transform(s));
+            }
+        }
+
+        let nums = self.as_vec_f32().unwrap();
+        if nums.len() != 16 {
+            panic!("transform expected a CSS transform string or 16 floats; got '{:?}'", self);
+        }
+        Some(LayoutTransform::row_major(nums[0], nums[1], nums[2], nums[3],
+                                        nums[4], nums[5], nums[6], nums[7],
+                                        nums[8], nums[9], nums[10], nums[11],
+                                        nums[12], nums[13], nums[14], nums[15]))
     }
 
     fn as_colorf(&self) -> Option<ColorF> {
@@ -143,6 +285,13 @@ impl YamlHelper for Yaml {
             return None;
         }
 
+        // { rect: ..., radii: ... } gives the radii separately
+        if self.as_hash().is_some() {
+            let rect = self["rect"].as_rect().expect(&format!("complex clip rect requires a rect, got '{:?}'", self));
+            let radii = self["radii"].as_border_radius().unwrap_or(BorderRadius::zero());
+            return Some(ComplexClipRegion::new(rect, radii));
+        }
+
         let nums = self.as_vec_f32().unwrap();
         match nums.len() {
             4 => Some(ComplexClipRegion::new(LayoutRect::new(LayoutPoint::new(nums[0], nums[1]), LayoutSize::new(nums[2], nums[3])),
@@ -167,13 +316,103 @@ impl YamlHelper for Yaml {
         }
     }
 
+    /// Reads a single radius for every corner, 4 circular radii (tl, tr, bl,
+    /// br), 8 elliptical ones as x, y pairs in the same order, or a map of
+    /// `tl`, `tr`, `br` and `bl` to [x, y] pairs, where missing corners are
+    /// square.
+    fn as_border_radius(&self) -> Option<BorderRadius> {
+        if self.is_badvalue() {
+            return None;
+        }
+
+        if self.as_hash().is_some() {
+            let corner = |name: &str| {
+                let corner = &self[name];
+                if corner.is_badvalue() {
+                    return LayoutSize::zero();
+                }
+                match corner.as_vec_f32() {
+                    Some(ref v) if v.len() == 2 => LayoutSize::new(v[0], v[1]),
+                    _ => panic!("radii corner {} expected [x, y], got '{:?}'", name, corner),
+                }
+            };
+            return Some(BorderRadius {
+                top_left: corner("tl"),
+                top_right: corner("tr"),
+                bottom_left: corner("bl"),
+                bottom_right: corner("br"),
+            });
+        }
+
+        if let Some(radius) = self.as_force_f32() {
+            return Some(BorderRadius::uniform(radius));
+        }
+
+        let nums = self.as_vec_f32().expect(&format!("radii expected a number, array, or map; got '{:?}'", self));
+        match nums.len() {
+            4 => Some(BorderRadius {
+                top_left: LayoutSize::new(nums[0], nums[0]),
+                top_right: LayoutSize::new(nums[1], nums[1]),
+                bottom_left: LayoutSize::new(nums[2], nums[2]),
+                bottom_right: LayoutSize::new(nums[3], nums[3]),
+            }),
+            8 => Some(BorderRadius {
+                top_left: LayoutSize::new(nums[0], nums[1]),
+                top_right: LayoutSize::new(nums[2], nums[3]),
+                bottom_left: LayoutSize::new(nums[4], nums[5]),
+                bottom_right: LayoutSize::new(nums[6], nums[7]),
+            }),
+            n => panic!("radii expected 1, 4, or 8 floats; got {} instead at '{:?}'", n, self),
+        }
+    }
+
+    /// Reads a CSS-style filter function such as `blur(4)` or
+    /// `hue-rotate(90deg)`.
+    fn as_filter_op(&self) -> Option<FilterOp> {
+        let filter = match self.as_str() {
+            Some(filter) => filter.trim(),
+            None => return None,
+        };
+        let open = filter.find('(').expect(&format!("expected a filter function, got '{}'", filter));
+        if !filter.ends_with(')') {
+            panic!("unterminated filter function '{}'", filter);
+        }
+        let function = filter[..open].trim();
+        let arg = filter[open + 1..filter.len() - 1].trim();
+        Some(match function {
+            "blur" => FilterOp::Blur(Au::from_f32_px(parse_css_number(arg))),
+            "brightness" => FilterOp::Brightness(parse_css_number(arg)),
+            "contrast" => FilterOp::Contrast(parse_css_number(arg)),
+            "grayscale" => FilterOp::Grayscale(parse_css_number(arg)),
+            "hue-rotate" => FilterOp::HueRotate(parse_css_angle(arg)),
+            "invert" => FilterOp::Invert(parse_css_number(arg)),
+            "opacity" => FilterOp::Opacity(parse_css_number(arg)),
+            "saturate" => FilterOp::Saturate(parse_css_number(arg)),
+            "sepia" => FilterOp::Sepia(parse_css_number(arg)),
+            _ => panic!("unknown filter function '{}'", function),
+        })
+    }
+
+    /// Reads an array of filters, keeping their order: as in CSS, each
+    /// filter is applied to the output of the one before it.
+    fn as_vec_filter_op(&self) -> Option<Vec<FilterOp>> {
+        self.as_vec().map(|filters| {
+            filters.iter()
+                .map(|filter| filter.as_filter_op().expect(&format!("expected a filter, got '{:?}'", filter)))
+                .collect()
+        })
+    }
+
     fn as_clip_region(&self, builder: &mut DisplayListBuilder) -> Option<ClipRegion> {
         if self.is_badvalue() {
             return None;
         }
 
-        // TODO add support for clip masks
-        // TODO add support for rounded rect clips
+        // a single rounded rect
+        if self.as_hash().is_some() {
+            let clip = self.as_complex_clip_rect().unwrap();
+            return Some(builder.new_clip_region(&clip.rect, vec![clip], None));
+        }
 
         // if it's not a vec, then assume it's a single rect
         if self.as_vec().is_none() {
@@ -194,3 +433,47 @@ impl YamlHelper for Yaml {
         Some(builder.new_clip_region(&bounds, clips, None))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use app_units::Au;
+    use super::YamlHelper;
+    use webrender_traits::*;
+    use yaml_rust::YamlLoader;
+
+    /// Pushes a stacking context with the filters given in YAML and returns
+    /// the filters it was pushed with, formatted for comparison.
+    fn pushed_filters(filters: &str) -> String {
+        let yaml = YamlLoader::load_from_str(filters).unwrap();
+        let filters = yaml[0].as_vec_filter_op().unwrap();
+
+        let mut builder = DisplayListBuilder::new(PipelineId(0, 0));
+        let bounds = LayoutRect::new(LayoutPoint::new(0.0, 0.0), LayoutSize::new(100.0, 100.0));
+        let clip = builder.new_clip_region(&bounds, Vec::new(), None);
+        builder.push_stacking_context(ScrollPolicy::Scrollable,
+                                      bounds,
+                                      clip,
+                                      0,
+                                      &LayoutTransform::identity(),
+                                      &LayoutTransform::identity(),
+                                      MixBlendMode::Normal,
+                                      filters);
+        builder.pop_stacking_context();
+
+        let filters_range = match builder.list[0].item {
+            SpecificDisplayItem::PushStackingContext(ref item) => item.stacking_context.filters,
+            _ => panic!("expected a stacking context"),
+        };
+        let (_, auxiliary_lists) = builder.finalize();
+        format!("{:?}", auxiliary_lists.filters(&filters_range))
+    }
+
+    #[test]
+    fn filters_are_pushed_in_order() {
+        let blur_first = pushed_filters("[blur(2), brightness(1.5)]");
+        let brightness_first = pushed_filters("[brightness(1.5), blur(2)]");
+        assert_eq!(blur_first, format!("{:?}", [FilterOp::Blur(Au::from_f32_px(2.0)), FilterOp::Brightness(1.5)]));
+        assert_eq!(brightness_first, format!("{:?}", [FilterOp::Brightness(1.5), FilterOp::Blur(Au::from_f32_px(2.0))]));
+        assert!(blur_first != brightness_first);
+    }
+}
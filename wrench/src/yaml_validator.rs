@@ -0,0 +1,101 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use std::cmp::min;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+/// The largest number of files checked at once.
+const MAX_THREADS: usize = 8;
+
+fn find_yaml_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(dir).expect(&format!("couldn't read directory {:?}", dir));
+    for entry in entries {
+        let path = entry.expect(&format!("couldn't read directory {:?}", dir)).path();
+        if path.is_dir() {
+            find_yaml_files(&path, files);
+        } else if path.extension().map_or(false, |extension| extension == "yaml") {
+            files.push(path);
+        }
+    }
+}
+
+/// Checks a YAML file by running `wrench show --validate` on it, which
+/// builds every document in the file with the real reader without
+/// rendering, and returns the errors it reported. The reader reports an
+/// error by panicking, so a failing file has one.
+fn validate_file(wrench_path: &Path, path: &Path) -> Vec<String> {
+    let output = match Command::new(wrench_path).arg("show").arg("--validate").arg(path).output() {
+        Ok(output) => output,
+        Err(error) => return vec![format!("couldn't run {:?}: {}", wrench_path, error)],
+    };
+    if output.status.success() {
+        return Vec::new();
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = stderr.lines().collect();
+    let errors: Vec<String> = lines.iter().enumerate()
+        .filter(|&(_, line)| line.contains("panicked at"))
+        .map(|(i, line)| match lines.get(i + 1) {
+            // newer toolchains print the message on the line after the
+            // location, which then ends with a colon
+            Some(message) if line.ends_with(':') => format!("{} {}", line, message),
+            _ => line.to_string(),
+        })
+        .collect();
+    if errors.is_empty() {
+        vec![format!("validation exited with {}", output.status)]
+    } else {
+        errors
+    }
+}
+
+/// Checks every `.yaml` file in `dir` and the directories below it with
+/// `wrench show --validate`, several files at a time, and prints the errors
+/// in each failing file followed by a summary. Returns whether every file
+/// passed.
+pub fn validate_dir(dir: &Path) -> bool {
+    let mut files = Vec::new();
+    find_yaml_files(dir, &mut files);
+    files.sort();
+    if files.is_empty() {
+        println!("no YAML files found in {:?}", dir);
+        return true;
+    }
+
+    let wrench_path = env::current_exe().expect("couldn't find the wrench executable");
+    let thread_count = min(files.len(), MAX_THREADS);
+    let chunk_size = (files.len() + thread_count - 1) / thread_count;
+    let threads: Vec<_> = files.chunks(chunk_size).map(|chunk| {
+        let chunk = chunk.to_vec();
+        let wrench_path = wrench_path.clone();
+        thread::spawn(move || {
+            chunk.into_iter().map(|path| {
+                let errors = validate_file(&wrench_path, &path);
+                (path, errors)
+            }).collect::<Vec<_>>()
+        })
+    }).collect();
+
+    let mut failures = 0;
+    for thread in threads {
+        for (path, errors) in thread.join().expect("validation thread panicked") {
+            if errors.is_empty() {
+                println!("PASS {}", path.display());
+                continue;
+            }
+            failures += 1;
+            println!("FAIL {}", path.display());
+            for error in &errors {
+                println!("    {}", error);
+            }
+        }
+    }
+    println!("{} passed, {} failed", files.len() - failures, failures);
+    failures == 0
+}